@@ -1,3 +1,144 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use cosmian_vm_client::{app_conf, client::EncryptedAppConf};
+use p256::ecdh::EphemeralSecret;
+use rand::rngs::OsRng;
+
+use crate::error::Error;
+
 pub mod service;
 
 pub(crate) const APP_CONF_FILENAME: &str = "app.conf";
+
+/// How long a key waits for the app configuration encrypted for it
+const APP_KEY_TTL: Duration = Duration::from_secs(300);
+
+/// The maximum number of keys waiting for an app configuration
+const MAX_PENDING_APP_KEYS: usize = 16;
+
+/// A key with its generation date
+type PendingKeys = HashMap<Vec<u8>, (EphemeralSecret, Instant)>;
+
+/// The ephemeral keys the app configuration is encrypted for, shared by all the workers
+///
+/// Each key is bound to the nonce it has been requested with, so another request can't
+/// replace it. A key decrypts a single configuration and expires after `APP_KEY_TTL`
+pub struct AppKeys {
+    keys: Mutex<PendingKeys>,
+    ttl: Duration,
+}
+
+impl Default for AppKeys {
+    fn default() -> Self {
+        Self::new(APP_KEY_TTL)
+    }
+}
+
+impl AppKeys {
+    /// Create the store of the keys expiring after `ttl`
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            keys: Mutex::default(),
+            ttl,
+        }
+    }
+
+    /// Lock the keys not expired yet
+    fn pending(&self) -> Result<MutexGuard<'_, PendingKeys>, Error> {
+        let mut keys = self
+            .keys
+            .lock()
+            .map_err(|_| Error::Unexpected("App keys poisoned".to_owned()))?;
+        keys.retain(|_, (_, generated_at)| generated_at.elapsed() < self.ttl);
+        Ok(keys)
+    }
+
+    /// Generate a new key for `nonce` and return its public key (SEC1 uncompressed point)
+    pub(crate) fn generate(&self, nonce: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut keys = self.pending()?;
+        if keys.contains_key(nonce) {
+            return Err(Error::BadRequest(
+                "An app key is already pending for this nonce".to_owned(),
+            ));
+        }
+        if keys.len() >= MAX_PENDING_APP_KEYS {
+            return Err(Error::BadRequest(format!(
+                "Too many app keys are pending: retry in {} seconds",
+                self.ttl.as_secs()
+            )));
+        }
+
+        let secret = EphemeralSecret::random(&mut OsRng);
+        let public_key = app_conf::public_key(&secret);
+        keys.insert(nonce.to_vec(), (secret, Instant::now()));
+        Ok(public_key)
+    }
+
+    /// Decrypt the app configuration `conf` with the key of its nonce and drop the key
+    ///
+    /// The key is kept if the decryption fails: a malformed request can't discard it
+    pub(crate) fn decrypt(&self, conf: &EncryptedAppConf) -> Result<Vec<u8>, Error> {
+        let mut keys = self.pending()?;
+
+        let (secret, _) = keys.get(&conf.key_nonce).ok_or_else(|| {
+            Error::BadRequest(
+                "No app key for this nonce (or expired): request one with `POST /app/key` first"
+                    .to_owned(),
+            )
+        })?;
+        let content =
+            app_conf::decrypt(secret, conf).map_err(|e| Error::BadRequest(e.to_string()))?;
+
+        keys.remove(&conf.key_nonce);
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cosmian_vm_client::app_conf;
+
+    use super::{AppKeys, MAX_PENDING_APP_KEYS};
+
+    #[test]
+    fn test_app_keys() {
+        let app_keys = AppKeys::default();
+        let public_key = app_keys.generate(&[1; 32]).unwrap();
+
+        // Another request can't replace the pending key
+        assert!(app_keys.generate(&[1; 32]).is_err());
+        let other_key = app_keys.generate(&[2; 32]).unwrap();
+
+        // A configuration encrypted for another key doesn't consume the key
+        let other_conf = app_conf::encrypt(&[1; 32], &other_key, b"other").unwrap();
+        assert!(app_keys.decrypt(&other_conf).is_err());
+
+        let conf = app_conf::encrypt(&[1; 32], &public_key, b"conf").unwrap();
+        assert_eq!(app_keys.decrypt(&conf).unwrap(), b"conf");
+
+        // A key decrypts a single configuration
+        assert!(app_keys.decrypt(&conf).is_err());
+
+        let other_conf = app_conf::encrypt(&[2; 32], &other_key, b"other").unwrap();
+        assert_eq!(app_keys.decrypt(&other_conf).unwrap(), b"other");
+
+        // The number of pending keys is bounded
+        for nonce in 0..MAX_PENDING_APP_KEYS {
+            app_keys.generate(&nonce.to_le_bytes()).unwrap();
+        }
+        assert!(app_keys.generate(&[3; 32]).is_err());
+
+        // The keys expire
+        let app_keys = AppKeys::new(Duration::ZERO);
+        let public_key = app_keys.generate(&[1; 32]).unwrap();
+        let conf = app_conf::encrypt(&[1; 32], &public_key, b"conf").unwrap();
+        assert!(app_keys.decrypt(&conf).is_err());
+    }
+}
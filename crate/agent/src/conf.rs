@@ -1,7 +1,15 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
+use cosmian_vm_client::{
+    pcr::{PcrBank, PcrSelection},
+    snapshot::SnapshotRules,
+};
 use rustls_pki_types::{pem::PemObject, CertificateDer};
 use serde::Deserialize;
+use tpm_quote::key::{TPM_AK_NVINDEX, TPM_EK_NVINDEX};
 
 use crate::{app::service::ServiceType, error::Error, VAR_PATH};
 
@@ -9,6 +17,12 @@ use crate::{app::service::ServiceType, error::Error, VAR_PATH};
 pub struct CosmianVmAgent {
     pub agent: Agent,
     pub app: Option<App>,
+    /// The rules selecting the files to hash during a snapshot
+    #[serde(default)]
+    pub snapshot: SnapshotRules,
+    /// The PCR banks and the PCRs used by the TPM quotes and the snapshots
+    #[serde(default)]
+    pub tpm: Tpm,
 }
 
 impl CosmianVmAgent {
@@ -45,6 +59,60 @@ pub struct Agent {
     ssl_private_key: PathBuf,
     /// Transmission interface with the TPM (ie: "/dev/tpmrm0")
     pub tpm_device: Option<PathBuf>,
+    /// The token (`Authorization: Bearer <token>`) granting the access to the admin endpoints
+    /// from outside the VM
+    pub admin_token: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(default)]
+pub struct Tpm {
+    /// The PCR banks a client can request a quote from, the first one being the default
+    /// (ie: ["sha256", "sha1"])
+    pub hash_banks: Vec<PcrBank>,
+    /// The PCRs quoted with the PCR extended by IMA and recorded in the snapshots,
+    /// unless the client requests other ones (ie: "0-7")
+    pub pcrs: PcrSelection,
+    /// The algorithm of the endorsement key and the attestation key
+    pub key_algorithm: TpmKeyAlgorithm,
+    /// The persistent handle of the endorsement key
+    pub ek_handle: u32,
+    /// The persistent handle of the attestation key
+    ///
+    /// Not configurable: the TPM quotes are signed by the attestation key persisted
+    /// at `TPM_AK_NVINDEX` (0x81000000)
+    #[serde(skip)]
+    pub ak_handle: u32,
+}
+
+impl Default for Tpm {
+    fn default() -> Self {
+        Self {
+            hash_banks: vec![PcrBank::Sha256],
+            pcrs: PcrSelection::default(),
+            key_algorithm: TpmKeyAlgorithm::default(),
+            ek_handle: TPM_EK_NVINDEX,
+            ak_handle: TPM_AK_NVINDEX,
+        }
+    }
+}
+
+/// The asymmetric algorithm of the TPM keys generated by the agent
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TpmKeyAlgorithm {
+    #[default]
+    Ecc,
+    Rsa,
+}
+
+impl fmt::Display for TpmKeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ecc => "ECC",
+            Self::Rsa => "RSA",
+        })
+    }
 }
 
 #[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -77,6 +145,35 @@ impl Agent {
     }
 }
 
+impl Tpm {
+    /// The PCR bank used when the client doesn't request one
+    #[must_use]
+    pub fn default_bank(&self) -> PcrBank {
+        self.hash_banks.first().copied().unwrap_or_default()
+    }
+
+    /// The PCR bank requested by a client, if enabled, or the default one
+    pub fn bank(&self, requested: Option<PcrBank>) -> Result<PcrBank, Error> {
+        match requested {
+            None => Ok(self.default_bank()),
+            Some(bank) if self.hash_banks.contains(&bank) => Ok(bank),
+            Some(bank) => Err(Error::BadRequest(format!(
+                "The PCR bank {bank} is not enabled in the agent configuration"
+            ))),
+        }
+    }
+
+    /// The PCRs requested by a client, or the configured ones
+    #[must_use]
+    pub fn pcrs<'a>(&'a self, requested: &'a PcrSelection) -> &'a PcrSelection {
+        if requested.is_empty() {
+            &self.pcrs
+        } else {
+            requested
+        }
+    }
+}
+
 impl App {
     #[must_use]
     pub fn app_storage(&self) -> PathBuf {
@@ -88,9 +185,13 @@ impl App {
 mod tests {
     use crate::app::service::ServiceType;
     use crate::{
-        conf::{Agent, App},
+        conf::{Agent, App, Tpm, TpmKeyAlgorithm},
         CosmianVmAgent,
     };
+    use cosmian_vm_client::{
+        pcr::{PcrBank, PcrSelection},
+        snapshot::SnapshotRules,
+    };
     use std::path::PathBuf;
 
     #[test]
@@ -103,11 +204,22 @@ mod tests {
             ssl_certificate = "data/cert.pem"
             ssl_private_key = "data/key.pem"
             tpm_device = "/dev/tpmrm0"
+            admin_token = "secret_token"
 
             [app]
             service_type = "supervisor"
             service_name = "cosmian_kms"
             app_storage = "data/app"
+
+            [snapshot]
+            include = ["/var/lib/**"]
+            max_file_size = 1048576
+            follow_symlinks = true
+
+            [tpm]
+            hash_banks = ["sha1", "sha384"]
+            pcrs = "0-7"
+            key_algorithm = "rsa"
             "#;
 
         let config: CosmianVmAgent = toml::from_str(cfg_str).unwrap();
@@ -120,15 +232,37 @@ mod tests {
                     ssl_certificate: PathBuf::from("data/cert.pem"),
                     ssl_private_key: PathBuf::from("data/key.pem"),
                     tpm_device: Some(PathBuf::from("/dev/tpmrm0")),
+                    admin_token: Some("secret_token".to_owned()),
                 },
                 app: Some(App {
                     service_type: ServiceType::Supervisor,
                     service_name: "cosmian_kms".to_owned(),
                     app_storage: PathBuf::from("data/app"),
-                })
+                }),
+                snapshot: SnapshotRules {
+                    include: vec!["/var/lib/**".to_owned()],
+                    max_file_size: Some(1_048_576),
+                    follow_symlinks: true,
+                    ..Default::default()
+                },
+                tpm: Tpm {
+                    hash_banks: vec![PcrBank::Sha1, PcrBank::Sha384],
+                    pcrs: (0..=7).collect(),
+                    key_algorithm: TpmKeyAlgorithm::Rsa,
+                    ek_handle: 0x8101_0001,
+                    ak_handle: 0x8100_0000,
+                },
             }
         );
 
+        assert_eq!(config.tpm.default_bank(), PcrBank::Sha1);
+        assert_eq!(
+            config.tpm.bank(Some(PcrBank::Sha384)).unwrap(),
+            PcrBank::Sha384
+        );
+        assert!(config.tpm.bank(Some(PcrBank::Sha256)).is_err());
+        assert_eq!(config.tpm.pcrs(&PcrSelection::default()), &config.tpm.pcrs);
+
         assert_eq!(
             config.agent.ssl_certificate(),
             PathBuf::from("/var/lib/cosmian_vm/data/cert.pem")
@@ -152,8 +286,11 @@ mod tests {
                     .join("data/cert.pem"),
                 ssl_private_key: PathBuf::from("data/key.pem"),
                 tpm_device: None,
+                admin_token: None,
             },
             app: None,
+            snapshot: SnapshotRules::default(),
+            tpm: Tpm::default(),
         };
 
         assert_eq!(
@@ -239,6 +376,7 @@ mod tests {
 
         let config: CosmianVmAgent = toml::from_str(cfg_str).unwrap();
 
+        assert_eq!(config.snapshot, SnapshotRules::default());
         assert_eq!(
             config.agent.ssl_certificate(),
             PathBuf::from("/data/cert.pem")
@@ -1,27 +1,69 @@
-use std::sync::Mutex;
+use std::{path::Path, sync::Mutex};
 
 use crate::{
-    app::APP_CONF_FILENAME,
+    app::{AppKeys, APP_CONF_FILENAME},
     error::{Error, ResponseWithError},
-    worker::snapshot::{self, order_snapshot, reset_snapshot, Snapshot},
-    CosmianVmAgent, DEFAULT_TPM_HASH_METHOD,
+    tpm::{activate_credential, ek_certificate, seal, unseal},
+    utils::read_pcr_values,
+    worker::snapshot::{
+        self, cancel_snapshot, order_snapshot, reset_snapshot, seal_snapshot, snapshot_status,
+        Snapshot,
+    },
+    CosmianVmAgent,
 };
 use actix_web::{
-    delete, get, post,
+    body::BoxBody,
+    delete, get,
+    http::header::{ACCEPT, AUTHORIZATION},
+    post,
     web::{Data, Json, Query},
-    HttpResponse,
+    Either, HttpRequest, HttpResponse, Responder,
 };
 
 use cosmian_vm_client::{
-    client::{AppConf, QuoteParam, TpmQuoteResponse},
-    snapshot::CosmianVmSnapshot,
+    client::{
+        app_key_binding_nonce, tpm_key_binding_nonce, ActivateCredentialParam, AppKeyParam,
+        AppKeyResponse, AttestationParam, AttestationResponse, Base64Bytes, EncryptedAppConf,
+        ImaBinaryParam, ImaBinaryResponse, QuoteParam, SealParam, SealedSecret, SnapshotParam,
+        TpmQuoteResponse, OCTET_STREAM,
+    },
+    pcr::{quoted_pcr_values, PcrBank, PcrSelection},
+    snapshot::{CosmianVmSnapshot, SnapshotStatus},
 };
-use ima::ima::{read_ima_ascii, read_ima_ascii_first_line, read_ima_binary, Ima};
+use ima::ima::{
+    binary_entry_offset, read_ima_ascii, read_ima_ascii_first_line, read_ima_binary, Ima,
+};
+use sha2::{Digest, Sha256};
 use tee_attestation::{forge_report_data_with_nonce, get_quote as tee_get_quote};
-use tpm_quote::{error::Error as TpmError, get_quote as tpm_get_quote};
+use tpm_quote::{
+    error::Error as TpmError, get_pcr_digest_from_quote, get_quote as tpm_get_quote, PcrHashMethod,
+};
 
 use tss_esapi::Context;
 
+/// Binary data sent as raw bytes if the client accepts `application/octet-stream`,
+/// as a base64 JSON string otherwise
+pub(crate) struct Binary(pub Vec<u8>);
+
+impl Responder for Binary {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let accepts_octet_stream = req
+            .headers()
+            .get_all(ACCEPT)
+            .filter_map(|accept| accept.to_str().ok())
+            .flat_map(|accept| accept.split(','))
+            .any(|media_type| media_type.trim().starts_with(OCTET_STREAM));
+
+        if accepts_octet_stream {
+            HttpResponse::Ok().content_type(OCTET_STREAM).body(self.0)
+        } else {
+            HttpResponse::Ok().json(Base64Bytes(self.0))
+        }
+    }
+}
+
 /// Get the IMA hashes list (ASCII format)
 ///
 /// Note: require root privileges
@@ -32,10 +74,35 @@ pub(crate) async fn get_ima_ascii() -> ResponseWithError<Json<String>> {
 
 /// Get the IMA hashes list (Binary format)
 ///
+/// The list is sent as raw bytes with `Accept: application/octet-stream`,
+/// as a base64 JSON string otherwise
+///
+/// If `from_entry=N` is given, only the entries from the entry number `N` are returned
+/// with the PCR value after the first `N` entries (in the bank `hash_bank` if given),
+/// so that a verifier which already holds these entries can replay only the new ones
+///
 /// Note: require root privileges
 #[get("/ima/binary")]
-pub(crate) async fn get_ima_binary() -> ResponseWithError<Json<Vec<u8>>> {
-    Ok(Json(read_ima_binary()?))
+pub(crate) async fn get_ima_binary(
+    ima_param: Query<ImaBinaryParam>,
+    conf: Data<CosmianVmAgent>,
+) -> ResponseWithError<Either<Binary, Json<ImaBinaryResponse>>> {
+    let ima_binary = read_ima_binary()?;
+
+    let Some(from_entry) = ima_param.from_entry else {
+        return Ok(Either::Left(Binary(ima_binary)));
+    };
+
+    let hash_method = PcrHashMethod::from(conf.tpm.bank(ima_param.hash_bank)?);
+    let offset = binary_entry_offset(&ima_binary, from_entry)?;
+    let previous_entries = Ima::try_from(&ima_binary[..offset])?;
+
+    Ok(Either::Right(Json(ImaBinaryResponse {
+        from_entry,
+        pcr_value: previous_entries.pcr_value(hash_method.clone())?,
+        pcr_value_hash_method: hash_method,
+        entries: ima_binary[offset..].to_vec(),
+    })))
 }
 
 /// Get a system snapshot.
@@ -43,15 +110,33 @@ pub(crate) async fn get_ima_binary() -> ResponseWithError<Json<Vec<u8>>> {
 /// If the snapshot is ready, return it with a HTTP status code `200 OK`
 /// If not, start a snapshot and return a HTTP status code `202 Accepted`
 ///
+/// The files unchanged since the previous snapshot are not hashed again,
+/// unless `full=true` is given
+///
+/// If `envelope=true` is given, the snapshot is wrapped into a `SnapshotEnvelope`
+/// holding a TEE quote which binds its content
+///
+/// If `pcrs` is given (e.g. `0-7`), the values of these PCRs are recorded in the snapshot
+/// instead of the values of the PCRs configured in the agent
+///
 /// Note: require root privileges
 #[get("/snapshot")]
 pub(crate) async fn get_snapshot(
+    snapshot_param: Query<SnapshotParam>,
     snapshot_worker: Data<Snapshot>,
+    certificate: Data<Vec<u8>>,
 ) -> ResponseWithError<HttpResponse> {
     match snapshot::get_snapshot(&snapshot_worker) {
-        Ok(Some(snapshot)) => Ok(HttpResponse::Ok().json(Some(snapshot))),
+        Ok(Some(stored)) if snapshot_param.envelope => Ok(
+            HttpResponse::Ok().json(Some(seal_snapshot(&snapshot_worker, stored, &certificate)?))
+        ),
+        Ok(Some(stored)) => Ok(HttpResponse::Ok().json(Some(stored.snapshot))),
         Ok(None) => {
-            order_snapshot(&snapshot_worker)?;
+            order_snapshot(
+                &snapshot_worker,
+                snapshot_param.full,
+                snapshot_param.pcrs.clone(),
+            )?;
             Ok(HttpResponse::Accepted().json(None::<CosmianVmSnapshot>))
         }
         Err(Error::SnapshotIsProcessing) => {
@@ -61,6 +146,25 @@ pub(crate) async fn get_snapshot(
     }
 }
 
+/// Get the progress of the snapshot being processed.
+#[get("/snapshot/status")]
+pub(crate) async fn get_snapshot_status(
+    snapshot_worker: Data<Snapshot>,
+) -> ResponseWithError<Json<SnapshotStatus>> {
+    Ok(Json(snapshot_status(&snapshot_worker)))
+}
+
+/// Cancel the snapshot being processed.
+///
+/// The next `GET /snapshot` fails until the snapshot is removed
+#[post("/snapshot/cancel")]
+pub(crate) async fn post_snapshot_cancel(
+    snapshot_worker: Data<Snapshot>,
+) -> ResponseWithError<Json<()>> {
+    cancel_snapshot(&snapshot_worker)?;
+    Ok(Json(()))
+}
+
 /// Remove the previously computed snapshot.
 #[delete("/snapshot")]
 pub(crate) async fn delete_snapshot(
@@ -71,28 +175,83 @@ pub(crate) async fn delete_snapshot(
 }
 
 /// Return the TEE quote
+///
+/// The quote is sent as raw bytes with `Accept: application/octet-stream`,
+/// as a base64 JSON string otherwise
 #[get("/quote/tee")]
 pub(crate) async fn get_tee_quote(
     data: Query<QuoteParam>,
     certificate: Data<Vec<u8>>,
-) -> ResponseWithError<Json<Vec<u8>>> {
-    let data = data.into_inner();
-    let report_data = forge_report_data_with_nonce(
-        &data.nonce.try_into().map_err(|_| {
-            Error::BadRequest("Nonce should be a 32 bytes string (hex encoded)".to_owned())
-        })?,
-        &certificate,
-    )?;
-    let quote = tee_get_quote(Some(&report_data))?;
-    Ok(Json(quote))
+) -> ResponseWithError<Binary> {
+    Ok(Binary(tee_quote(data.into_inner().nonce, &certificate)?))
 }
 
 /// Return the TPM quote
+///
+/// The PCRs `pcrs` (e.g. `0-7`), or the PCRs configured in the agent, are quoted in addition
+/// to the PCR extended by IMA. The PCR bank is `hash_bank` if given and enabled in the agent
+/// configuration, the first configured bank otherwise
 #[get("/quote/tpm")]
 pub(crate) async fn get_tpm_quote(
     quote_param: Query<QuoteParam>,
+    conf: Data<CosmianVmAgent>,
     tpm_context: Data<Mutex<Option<Context>>>,
 ) -> ResponseWithError<Json<TpmQuoteResponse>> {
+    let bank = conf.tpm.bank(quote_param.hash_bank)?;
+
+    let mut tpm_context = tpm_context
+        .lock()
+        .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
+
+    let tpm_context = tpm_context.as_mut().ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+
+    Ok(Json(tpm_quote(
+        tpm_context,
+        conf.agent.tpm_device.as_deref(),
+        &quote_param.nonce,
+        conf.tpm.pcrs(&quote_param.pcrs),
+        bank,
+    )?))
+}
+
+/// Return the certificate (DER) of the TPM endorsement key, provisioned by the TPM manufacturer
+///
+/// The certificate is sent as raw bytes with `Accept: application/octet-stream`,
+/// as a base64 JSON string otherwise
+#[get("/tpm/ek_certificate")]
+pub(crate) async fn get_tpm_ek_certificate(
+    conf: Data<CosmianVmAgent>,
+    tpm_context: Data<Mutex<Option<Context>>>,
+) -> ResponseWithError<Binary> {
+    let mut tpm_context = tpm_context
+        .lock()
+        .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
+
+    let tpm_context = tpm_context.as_mut().ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+
+    Ok(Binary(ek_certificate(tpm_context, &conf.tpm)?))
+}
+
+/// Return the credential recovered by the TPM from a credential encrypted for its
+/// endorsement key and bound to the name of its attestation key
+///
+/// It proves to the verifier that the attestation key signing the TPM quotes lives in the
+/// TPM of the endorsement key
+#[post("/tpm/activate_credential")]
+pub(crate) async fn post_tpm_activate_credential(
+    data: Json<ActivateCredentialParam>,
+    conf: Data<CosmianVmAgent>,
+    tpm_context: Data<Mutex<Option<Context>>>,
+) -> ResponseWithError<Json<Base64Bytes>> {
+    let ActivateCredentialParam {
+        credential_blob,
+        encrypted_secret,
+    } = data.into_inner();
+
     let mut tpm_context = tpm_context
         .lock()
         .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
@@ -101,44 +260,310 @@ pub(crate) async fn get_tpm_quote(
         Error::Unexpected("The agent is not configured to support TPM".to_owned())
     })?;
 
-    let quote_param = quote_param.into_inner();
+    Ok(Json(Base64Bytes(activate_credential(
+        tpm_context,
+        &conf.tpm,
+        credential_blob,
+        encrypted_secret,
+    )?)))
+}
 
-    if quote_param.nonce.len() > 64 {
+/// Seal a secret (128 bytes max) to the current values of PCRs of the TPM
+///
+/// The PCRs `pcrs` and the PCR bank `hash_bank` are selected as in `GET /quote/tpm`.
+/// Sealing to the PCR extended by IMA makes the secret unusable as soon as a new file
+/// is measured
+///
+/// Only allowed from the VM itself or with the admin token of the agent configuration
+#[post("/secret/seal")]
+pub(crate) async fn post_secret_seal(
+    req: HttpRequest,
+    data: Json<SealParam>,
+    conf: Data<CosmianVmAgent>,
+    tpm_context: Data<Mutex<Option<Context>>>,
+) -> ResponseWithError<Json<SealedSecret>> {
+    check_admin_access(&req, conf.agent.admin_token.as_deref())?;
+
+    let SealParam {
+        secret,
+        pcrs,
+        hash_bank,
+    } = data.into_inner();
+    let bank = conf.tpm.bank(hash_bank)?;
+    let pcrs = conf.tpm.pcrs(&pcrs).clone();
+
+    let mut tpm_context = tpm_context
+        .lock()
+        .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
+
+    let tpm_context = tpm_context.as_mut().ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+
+    Ok(Json(seal(tpm_context, secret, pcrs, bank)?))
+}
+
+/// Unseal a secret sealed by `POST /secret/seal`
+///
+/// The request is forbidden if the PCRs no longer have the values they had when the secret
+/// was sealed
+///
+/// Only allowed from the VM itself or with the admin token of the agent configuration
+#[post("/secret/unseal")]
+pub(crate) async fn post_secret_unseal(
+    req: HttpRequest,
+    data: Json<SealedSecret>,
+    conf: Data<CosmianVmAgent>,
+    tpm_context: Data<Mutex<Option<Context>>>,
+) -> ResponseWithError<Json<Base64Bytes>> {
+    check_admin_access(&req, conf.agent.admin_token.as_deref())?;
+
+    let mut tpm_context = tpm_context
+        .lock()
+        .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
+
+    let tpm_context = tpm_context.as_mut().ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+
+    Ok(Json(Base64Bytes(unseal(tpm_context, data.into_inner())?)))
+}
+
+/// Allow the request if it comes from the VM itself or holds the admin token
+/// (`Authorization: Bearer <token>`)
+fn check_admin_access(req: &HttpRequest, admin_token: Option<&str>) -> Result<(), Error> {
+    if req
+        .peer_addr()
+        .is_some_and(|addr| addr.ip().to_canonical().is_loopback())
+    {
+        return Ok(());
+    }
+
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+
+    match (admin_token, token) {
+        // Compare the digests to not leak the token length or prefix through the timing
+        (Some(admin_token), Some(token))
+            if !admin_token.is_empty()
+                && Sha256::digest(admin_token) == Sha256::digest(token.trim()) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::Forbidden(
+            "Only allowed from the VM itself or with the admin token".to_owned(),
+        )),
+    }
+}
+
+/// Return the TEE quote, the TPM quote and the IMA log bound to the same nonce
+///
+/// The IMA log can grow at any time, even while the TPM is locked: the TPM quote is taken
+/// first, then the IMA log is read and truncated to the entries extended in the quoted PCR.
+/// The returned entries and quote always match, without any retry from the verifier
+///
+/// The report data of the TEE quote binds the public key of the TPM attestation key
+/// with the nonce and the TLS certificate (see `tpm_key_binding_nonce`)
+///
+/// If `from_entry` is given, only the IMA entries from this entry number are returned
+/// with the PCR value after the previous entries. If the log is shorter than `from_entry`
+/// (e.g. the VM rebooted), the whole log is returned: the response `from_entry` is then 0
+///
+/// The PCRs `pcrs` and the PCR bank `hash_bank` are selected as in `GET /quote/tpm`
+#[post("/attestation")]
+pub(crate) async fn post_attestation(
+    data: Json<AttestationParam>,
+    conf: Data<CosmianVmAgent>,
+    certificate: Data<Vec<u8>>,
+    tpm_context: Data<Mutex<Option<Context>>>,
+) -> ResponseWithError<Json<AttestationResponse>> {
+    let AttestationParam {
+        nonce,
+        from_entry,
+        pcrs,
+        hash_bank,
+    } = data.into_inner();
+    let bank = conf.tpm.bank(hash_bank)?;
+
+    if nonce.len() != 32 {
+        return Err(Error::BadRequest(
+            "Nonce should be a 32 bytes string (hex encoded)".to_owned(),
+        ));
+    }
+
+    let mut tpm_context = tpm_context
+        .lock()
+        .map_err(|_| Error::Unexpected("TPM already in use".to_owned()))?;
+
+    let tpm_context = tpm_context.as_mut().ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+
+    let tpm_quote = tpm_quote(
+        tpm_context,
+        conf.agent.tpm_device.as_deref(),
+        &nonce,
+        conf.tpm.pcrs(&pcrs),
+        bank,
+    )?;
+    let quote_pcrs_digest = get_pcr_digest_from_quote(&tpm_quote.quote)?;
+
+    let ima_binary = read_ima_binary()?;
+    let ima = Ima::try_from(ima_binary.as_slice())?;
+    let pcr_values = ima.pcr_values(bank.into());
+
+    // The number of entries extended in the PCR when the quote was taken
+    let mut quoted_entries = None;
+    for (entries, pcr_value) in pcr_values.iter().enumerate().rev() {
+        let quoted = quoted_pcr_values(&tpm_quote.pcr_values, ima.pcr_id() as u8, pcr_value)
+            .map_err(|e| Error::Unexpected(format!("Invalid PCR value: {e}")))?;
+        if Sha256::digest(quoted)[..] == quote_pcrs_digest[..] {
+            quoted_entries = Some(entries);
+            break;
+        }
+    }
+    let quoted_entries = quoted_entries.ok_or_else(|| {
+        Error::Unexpected("The IMA log doesn't match the PCRs quoted by the TPM".to_owned())
+    })?;
+
+    let from_entry = match from_entry {
+        Some(from_entry) if from_entry <= quoted_entries => from_entry,
+        Some(from_entry) => {
+            tracing::info!(
+                "The IMA log has only {quoted_entries} entries (entry {from_entry} requested): \
+                 returning the whole log"
+            );
+            0
+        }
+        None => 0,
+    };
+
+    let ima = ImaBinaryResponse {
+        from_entry,
+        pcr_value: pcr_values[from_entry].clone(),
+        pcr_value_hash_method: bank.into(),
+        entries: ima_binary[binary_entry_offset(&ima_binary, from_entry)?
+            ..binary_entry_offset(&ima_binary, quoted_entries)?]
+            .to_vec(),
+    };
+
+    Ok(Json(AttestationResponse {
+        tee_quote: tee_quote(
+            tpm_key_binding_nonce(&nonce, &tpm_quote.public_key).to_vec(),
+            &certificate,
+        )?,
+        tpm_quote,
+        ima,
+    }))
+}
+
+/// Get a TEE quote whose report data binds the `nonce` and the TLS `certificate`
+fn tee_quote(nonce: Vec<u8>, certificate: &[u8]) -> Result<Vec<u8>, Error> {
+    let report_data = forge_report_data_with_nonce(
+        &nonce.try_into().map_err(|_| {
+            Error::BadRequest("Nonce should be a 32 bytes string (hex encoded)".to_owned())
+        })?,
+        certificate,
+    )?;
+    Ok(tee_get_quote(Some(&report_data))?)
+}
+
+/// Get a TPM quote of the PCR extended by IMA and of the PCRs `pcrs` in the PCR bank `bank`
+///
+/// The values of the PCRs `pcrs` are read after the quote: a PCR extended in between
+/// doesn't match the quote anymore and fails the verification
+fn tpm_quote(
+    tpm_context: &mut Context,
+    tpm_device: Option<&Path>,
+    nonce: &[u8],
+    pcrs: &PcrSelection,
+    bank: PcrBank,
+) -> Result<TpmQuoteResponse, Error> {
+    if nonce.len() > 64 {
         return Err(Error::Tpm(TpmError::AttestationError(
             "Nonce too long (> 64 bytes)".to_owned(),
         )));
     }
 
-    let pcr_slot = Ima::try_from(read_ima_ascii_first_line()?.as_str())?.pcr_id();
+    let pcr_slot = Ima::try_from(read_ima_ascii_first_line()?.as_str())?.pcr_id() as u8;
     tracing::debug!(
-        "Cosmian VM agent: get_tpm_quote: pcr_slot: {pcr_slot:?}, nonce: {:?}, hash method: {:?}",
-        quote_param.nonce,
-        DEFAULT_TPM_HASH_METHOD
+        "Cosmian VM agent: get_tpm_quote: pcr_slot: {pcr_slot:?}, pcrs: {pcrs}, nonce: {nonce:?}, bank: {bank}"
     );
 
+    let mut pcr_list = pcrs.clone();
+    pcr_list.insert(pcr_slot);
     let (quote, signature, public_key) = tpm_get_quote(
         tpm_context,
-        &[pcr_slot as u8],
-        Some(&quote_param.nonce),
-        DEFAULT_TPM_HASH_METHOD,
+        &pcr_list.iter().collect::<Vec<_>>(),
+        Some(nonce),
+        bank.into(),
     )?;
 
-    Ok(Json(TpmQuoteResponse {
+    let tpm_device = tpm_device.ok_or_else(|| {
+        Error::Unexpected("The agent is not configured to support TPM".to_owned())
+    })?;
+    let pcr_values = read_pcr_values(tpm_device, pcrs.iter().filter(|pcr| *pcr != pcr_slot), bank)?;
+
+    Ok(TpmQuoteResponse {
         quote,
         signature,
         public_key,
-        pcr_value_hash_method: DEFAULT_TPM_HASH_METHOD,
+        pcr_value_hash_method: bank.into(),
+        pcr_values,
+    })
+}
+
+/// Generate the ephemeral key the app configuration of `POST /app/init` is encrypted for
+///
+/// The report data of the returned TEE quote binds its public key with the nonce and the
+/// TLS certificate (see `app_key_binding_nonce`): the client checks that the key lives in the
+/// confidential VM before encrypting the configuration. The key is bound to the nonce:
+/// it waits for the configuration encrypted for it until it expires (see `AppKeys`)
+#[post("/app/key")]
+pub(crate) async fn post_app_key(
+    data: Json<AppKeyParam>,
+    conf: Data<CosmianVmAgent>,
+    certificate: Data<Vec<u8>>,
+    app_keys: Data<AppKeys>,
+) -> ResponseWithError<Json<AppKeyResponse>> {
+    if conf.app.is_none() {
+        // No app configuration provided
+        return Err(Error::BadRequest(
+            "No app section provided in Cosmian VM Agent configuration file".to_owned(),
+        ));
+    }
+
+    if data.nonce.len() != 32 {
+        return Err(Error::BadRequest(
+            "Nonce should be 32 bytes long".to_owned(),
+        ));
+    }
+
+    let public_key = app_keys.generate(&data.nonce)?;
+    let tee_quote = tee_quote(
+        app_key_binding_nonce(&data.nonce, &public_key).to_vec(),
+        &certificate,
+    )?;
+
+    Ok(Json(AppKeyResponse {
+        public_key,
+        tee_quote,
     }))
 }
 
-/// Write the app configuration and starts the app
+/// Decrypt the app configuration, write it and start the app
+///
+/// The configuration is encrypted for the key generated by `POST /app/key` for its nonce,
+/// which is dropped once decrypted: a new key is required for each initialization
 #[post("/app/init")]
 pub(crate) async fn init_app(
-    data: Json<AppConf>,
+    data: Json<EncryptedAppConf>,
     conf: Data<CosmianVmAgent>,
+    app_keys: Data<AppKeys>,
 ) -> ResponseWithError<Json<()>> {
-    let app_conf_param = data.into_inner();
-
     let Some(app_conf_agent) = &conf.app else {
         // No app configuration provided
         return Err(Error::BadRequest(
@@ -146,6 +571,8 @@ pub(crate) async fn init_app(
         ));
     };
 
+    let content = app_keys.decrypt(&data)?;
+
     let app_storage = app_conf_agent.app_storage();
     if !std::path::Path::new(&app_storage).exists() {
         std::fs::create_dir_all(&app_storage).map_err(|e| {
@@ -156,7 +583,7 @@ pub(crate) async fn init_app(
 
     // Write app conf
     let app_conf_filepath = app_storage.join(APP_CONF_FILENAME);
-    std::fs::write(&app_conf_filepath, app_conf_param.content).map_err(|e| {
+    std::fs::write(&app_conf_filepath, content).map_err(|e| {
         tracing::error!("cannot write app conf file {app_conf_filepath:?}");
         Error::IO(e)
     })?;
@@ -193,3 +620,68 @@ pub(crate) async fn restart_app(conf: Data<CosmianVmAgent>) -> ResponseWithError
 
     Ok(Json(()))
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::header::CONTENT_TYPE, test::TestRequest, Responder};
+    use cosmian_vm_client::client::OCTET_STREAM;
+
+    use super::{check_admin_access, Binary};
+    use crate::error::Error;
+
+    #[actix_web::test]
+    async fn test_binary_negotiation() {
+        let request = TestRequest::default()
+            .insert_header(("Accept", "application/json, application/octet-stream"))
+            .to_http_request();
+        let response = Binary(vec![1, 2, 255]).respond_to(&request);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), OCTET_STREAM);
+        assert_eq!(
+            to_bytes(response.into_body()).await.unwrap(),
+            vec![1, 2, 255]
+        );
+
+        let request = TestRequest::default().to_http_request();
+        let response = Binary(vec![1, 2, 255]).respond_to(&request);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "\"AQL/\"");
+    }
+
+    #[test]
+    fn test_check_admin_access() {
+        let local = TestRequest::default()
+            .peer_addr("127.0.0.1:50000".parse().unwrap())
+            .to_http_request();
+        assert!(check_admin_access(&local, None).is_ok());
+
+        let local = TestRequest::default()
+            .peer_addr("[::ffff:127.0.0.1]:50000".parse().unwrap())
+            .to_http_request();
+        assert!(check_admin_access(&local, None).is_ok());
+
+        let remote = TestRequest::default()
+            .peer_addr("10.0.0.2:50000".parse().unwrap())
+            .to_http_request();
+        assert!(matches!(
+            check_admin_access(&remote, Some("token")),
+            Err(Error::Forbidden(_))
+        ));
+
+        let remote = TestRequest::default()
+            .peer_addr("10.0.0.2:50000".parse().unwrap())
+            .insert_header(("Authorization", "Bearer token"))
+            .to_http_request();
+        assert!(check_admin_access(&remote, Some("token")).is_ok());
+        assert!(check_admin_access(&remote, Some("other_token")).is_err());
+        assert!(check_admin_access(&remote, None).is_err());
+
+        let remote = TestRequest::default()
+            .peer_addr("10.0.0.2:50000".parse().unwrap())
+            .insert_header(("Authorization", "Bearer "))
+            .to_http_request();
+        assert!(check_admin_access(&remote, Some("")).is_err());
+    }
+}
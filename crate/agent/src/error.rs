@@ -14,12 +14,18 @@ pub enum Error {
     Certificate(String),
     #[error("A snapshot is currently processing (hold on before processing other actions)")]
     SnapshotIsProcessing,
+    #[error("The snapshot has been cancelled")]
+    SnapshotCancelled,
+    #[error("No snapshot is currently processing")]
+    NoSnapshotProcessing,
     #[error("{0}")]
     Command(String),
     #[error("{0}")]
     Configuration(String),
     #[error("{0}")]
     Cryptography(String),
+    #[error("{0}")]
+    Forbidden(String),
     #[error(transparent)]
     HexParsing(#[from] hex::FromHexError),
     #[error(transparent)]
@@ -34,6 +40,8 @@ pub enum Error {
     TeeAttestation(#[from] tee_attestation::error::Error),
     #[error(transparent)]
     Tpm(#[from] tpm_quote::error::Error),
+    #[error(transparent)]
+    TssEsapi(#[from] tss_esapi::Error),
     #[error("{0}")]
     Unexpected(String),
     #[error(transparent)]
@@ -54,10 +62,15 @@ impl ResponseError for Error {
             | Self::Serialization(_)
             | Self::TeeAttestation(_)
             | Self::Tpm(_)
+            | Self::TssEsapi(_)
             | Self::Unexpected(_)
             | Self::WalkDir(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
-            Self::SnapshotIsProcessing => StatusCode::CONFLICT,
+            Self::SnapshotIsProcessing | Self::SnapshotCancelled => StatusCode::CONFLICT,
+
+            Self::NoSnapshotProcessing => StatusCode::NOT_FOUND,
+
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
 
             Self::BadRequest(_) | Self::BadUserAgent(_) => StatusCode::BAD_REQUEST,
         }
@@ -3,12 +3,12 @@ use std::{fs::File, path::Path};
 use self::{
     certificate::generate_self_signed_cert, luks::generate_encrypted_fs, tpm::generate_tpm_keys,
 };
-use crate::{conf::CosmianVmAgent, error::Error, VAR_PATH};
+use crate::{conf::CosmianVmAgent, error::Error, utils::create_tpm_context, VAR_PATH};
 use const_format::formatcp;
 
 mod certificate;
 mod luks;
-mod tpm;
+pub(crate) mod tpm;
 
 /// A file we store to remember the agent has already been configured once
 const AGENT_INITIALIZED_CACHE_PATH: &str = formatcp!("{VAR_PATH}/cache.init");
@@ -36,7 +36,13 @@ pub fn initialize_agent(conf: &CosmianVmAgent) -> Result<(), Error> {
 
     // Generate TPM keys if tpm is enabled in the config file
     if let Some(tpm_device) = &conf.agent.tpm_device {
-        generate_tpm_keys(tpm_device)?;
+        if !tpm_device.exists() {
+            return Err(Error::Configuration(format!(
+                "TPM device path unknown: {tpm_device:?} "
+            )));
+        }
+        let mut tpm_context = create_tpm_context(tpm_device)?;
+        generate_tpm_keys(&mut tpm_context, &conf.tpm)?;
     } else {
         tracing::warn!("No TPM configuration found: TPM generation keys skipped!");
         tracing::warn!(
@@ -1,97 +1,145 @@
+use crate::conf::{Tpm, TpmKeyAlgorithm};
 use crate::error::Error;
-use crate::utils::call;
+use crate::tpm::load_persistent;
 
-use std::path::Path;
-use std::process::Command;
-
-use tpm_quote::key::{TPM_AK_NVINDEX, TPM_EK_NVINDEX};
+use tss_esapi::{
+    abstraction::{ak, ek, DefaultKey},
+    handles::{KeyHandle, PersistentTpmHandle},
+    interface_types::{
+        algorithm::{HashingAlgorithm, SignatureSchemeAlgorithm},
+        dynamic_handles::Persistent,
+        resource_handles::Provision,
+    },
+    structures::Public,
+    Context,
+};
 
 /// Generate the TPM keys during the first startup of the agent
-/// - Ignore generation if already done previously
-/// - Raise an error if no TPM detected
-///
-/// Note: this function should be replace in a near feature (waiting for a patch in the tpm lib)
-pub(crate) fn generate_tpm_keys(tpm_device_path: &Path) -> Result<(), Error> {
-    let tpm_ek_nvindex = &TPM_EK_NVINDEX.to_string();
-    let tpm_ak_nvindex = &TPM_AK_NVINDEX.to_string();
+/// - Ignore the generation of a key already persisted at its handle
+/// - Raise an error if a persisted key doesn't use the configured algorithm
+pub(crate) fn generate_tpm_keys(context: &mut Context, tpm: &Tpm) -> Result<(), Error> {
+    // Create EK and make it persistent
+    let ek = if let Some(ek) = load_persistent(context, tpm.ek_handle)? {
+        check_algorithm(context, ek, tpm.key_algorithm, "EK")?;
+        ek
+    } else {
+        tracing::info!("Generating TPM EK ({})...", tpm.key_algorithm);
+        let ek = ek::create_ek_object(context, tpm.key_algorithm.into(), None::<DefaultKey>)?;
+        persist(context, ek, tpm.ek_handle)?
+    };
 
-    if !tpm_device_path.exists() {
-        return Err(Error::Configuration(format!(
-            "TPM device path unknown: {tpm_device_path:?} "
-        )));
+    // Create AK under the EK and make it persistent
+    if let Some(ak) = load_persistent(context, tpm.ak_handle)? {
+        check_algorithm(context, ak, tpm.key_algorithm, "AK")?;
+    } else {
+        tracing::info!("Generating TPM AK ({})...", tpm.key_algorithm);
+        let signature_scheme = match tpm.key_algorithm {
+            TpmKeyAlgorithm::Ecc => SignatureSchemeAlgorithm::EcDsa,
+            TpmKeyAlgorithm::Rsa => SignatureSchemeAlgorithm::RsaSsa,
+        };
+        let ak = ak::create_ak(
+            context,
+            ek,
+            HashingAlgorithm::Sha256,
+            signature_scheme,
+            None,
+            None::<DefaultKey>,
+        )?;
+        let ak = ak::load_ak(context, ek, None, ak.out_private, ak.out_public)?;
+        persist(context, ak, tpm.ak_handle)?;
     }
 
-    // Verify the keys has not been already generated
-    match Command::new("tpm2_readpublic")
-        .args(["-c", tpm_ek_nvindex])
-        .output()
-    {
-        Ok(output) => {
-            if output.status.success() {
-                return Ok(());
-            }
+    Ok(())
+}
+
+/// Make the transient key `key` persistent at `handle` and flush it
+fn persist(context: &mut Context, key: KeyHandle, handle: u32) -> Result<KeyHandle, Error> {
+    let persistent = Persistent::Persistent(PersistentTpmHandle::new(handle)?);
+    let persisted = context.execute_with_nullauth_session(|ctx| {
+        ctx.evict_control(Provision::Owner, key.into(), persistent)
+    })?;
+    context.flush_context(key.into())?;
+
+    Ok(persisted.into())
+}
+
+/// Check the persisted key `key` uses the configured algorithm
+fn check_algorithm(
+    context: &mut Context,
+    key: KeyHandle,
+    algorithm: TpmKeyAlgorithm,
+    name: &str,
+) -> Result<(), Error> {
+    let (public, _, _) = context.read_public(key)?;
+    match (public, algorithm) {
+        (Public::Ecc { .. }, TpmKeyAlgorithm::Ecc) | (Public::Rsa { .. }, TpmKeyAlgorithm::Rsa) => {
+            Ok(())
         }
-        Err(e) => return Err(Error::Command(e.to_string())),
+        _ => Err(Error::Configuration(format!(
+            "The TPM {name} already persisted is not an {algorithm} key"
+        ))),
     }
+}
 
-    // Otherwise generate them
-    //
-    // # create EK and make it persistent
-    // sudo tpm2_createek --ek-context=ek.ctx --key-algorithm=ecc --public=ek.pub --format=pem
-    // sudo tpm2_evictcontrol --hierarchy=o --object-context=ek.ctx --output=ek.handle
-    // # create AK and make it persistent
-    // sudo tpm2_createak --ek-context=ek.handle --ak-context=ak.ctx --key-algorithm=ecc --hash-algorithm=sha256 --public=ak.pub --format pem --ak-name=ak.name
-    // sudo tpm2_evictcontrol --hierarchy=o --object-context=ak.ctx --output=ak.handle
+#[cfg(test)]
+mod tests {
+    use super::generate_tpm_keys;
+    use crate::conf::{Tpm, TpmKeyAlgorithm};
+    use crate::error::Error;
+    use crate::tpm::{
+        load_persistent,
+        tests::{evict, simulator_context},
+    };
 
-    // Create EK and make it persistent
-    tracing::info!("Generating TPM EK & AK...");
-    call(
-        "tpm2_createek",
-        &[
-            "--ek-context=/tmp/ek.ctx",
-            "--key-algorithm=ecc",
-            "--public=/tmp/ek.pub",
-            "--format=pem",
-        ],
-        false,
-    )?;
+    #[test]
+    #[ignore = "require a swtpm simulator (TEST_TCTI)"]
+    fn test_generate_tpm_keys() {
+        let mut context = simulator_context();
 
-    call(
-        "tpm2_evictcontrol",
-        &[
-            "--hierarchy=o",
-            "--object-context=/tmp/ek.ctx",
-            "--output=/tmp/ek.handle",
-            tpm_ek_nvindex,
-        ],
-        false,
-    )?;
+        for (key_algorithm, ek_handle, ak_handle, other_algorithm) in [
+            (
+                TpmKeyAlgorithm::Ecc,
+                0x8101_0101,
+                0x8100_0101,
+                TpmKeyAlgorithm::Rsa,
+            ),
+            (
+                TpmKeyAlgorithm::Rsa,
+                0x8101_0102,
+                0x8100_0102,
+                TpmKeyAlgorithm::Ecc,
+            ),
+        ] {
+            evict(&mut context, ek_handle);
+            evict(&mut context, ak_handle);
 
-    // Create AK and make it persistent
-    call(
-        "tpm2_createak",
-        &[
-            "--ek-context=/tmp/ek.handle",
-            "--ak-context=/tmp/ak.ctx",
-            "--key-algorithm=ecc",
-            "--hash-algorithm=sha256",
-            "--public=/tmp/ak.pub",
-            "--format=pem",
-            "--ak-name=/tmp/ak.name",
-        ],
-        false,
-    )?;
+            let tpm = Tpm {
+                key_algorithm,
+                ek_handle,
+                ak_handle,
+                ..Tpm::default()
+            };
+            generate_tpm_keys(&mut context, &tpm).unwrap();
+            let ak = load_persistent(&mut context, ak_handle).unwrap().unwrap();
+            let (_, ak_name, _) = context.read_public(ak).unwrap();
 
-    call(
-        "tpm2_evictcontrol",
-        &[
-            "--hierarchy=o",
-            "--object-context=/tmp/ak.ctx",
-            "--output=/tmp/ak.handle",
-            tpm_ak_nvindex,
-        ],
-        false,
-    )?;
+            // Already generated keys are kept
+            generate_tpm_keys(&mut context, &tpm).unwrap();
+            let ak = load_persistent(&mut context, ak_handle).unwrap().unwrap();
+            assert_eq!(context.read_public(ak).unwrap().1, ak_name);
 
-    Ok(())
+            // Keys persisted with another algorithm are rejected
+            let tpm = Tpm {
+                key_algorithm: other_algorithm,
+                ..tpm
+            };
+            assert!(matches!(
+                generate_tpm_keys(&mut context, &tpm),
+                Err(Error::Configuration(_))
+            ));
+
+            evict(&mut context, ek_handle);
+            evict(&mut context, ak_handle);
+        }
+    }
 }
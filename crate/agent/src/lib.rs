@@ -9,6 +9,7 @@ use actix_web::{
 };
 use std::sync::Mutex;
 
+use app::AppKeys;
 use conf::CosmianVmAgent;
 use const_format::formatcp;
 use error::Error;
@@ -27,13 +28,13 @@ pub mod endpoints;
 pub mod error;
 /// Related to tasks to process at the first Cosmian VM start
 pub mod init;
+/// Use of the TPM endorsement key and attestation key
+pub mod tpm;
 pub mod user_agent;
 pub mod utils;
 /// Workers processing async tasks
 pub mod worker;
 
-const DEFAULT_TPM_HASH_METHOD: tpm_quote::PcrHashMethod = tpm_quote::PcrHashMethod::Sha256;
-
 pub const BIN_PATH: &str = "/usr/local/bin/";
 pub const VAR_PATH: &str = "/var/lib/cosmian_vm";
 pub const ETC_PATH: &str = "/etc/cosmian_vm";
@@ -44,15 +45,24 @@ pub fn endpoints(cfg: &mut ServiceConfig) {
     cfg.service(endpoints::get_ima_ascii);
     cfg.service(endpoints::get_ima_binary);
     cfg.service(endpoints::get_snapshot);
+    cfg.service(endpoints::get_snapshot_status);
     cfg.service(endpoints::get_tee_quote);
+    cfg.service(endpoints::get_tpm_ek_certificate);
     cfg.service(endpoints::get_tpm_quote);
     cfg.service(endpoints::init_app);
+    cfg.service(endpoints::post_app_key);
+    cfg.service(endpoints::post_attestation);
+    cfg.service(endpoints::post_secret_seal);
+    cfg.service(endpoints::post_secret_unseal);
+    cfg.service(endpoints::post_snapshot_cancel);
+    cfg.service(endpoints::post_tpm_activate_credential);
     cfg.service(endpoints::restart_app);
 }
 
 pub fn config(
     conf: CosmianVmAgent,
     snapshot_worker: Arc<Snapshot>,
+    app_keys: Arc<AppKeys>,
 ) -> impl FnOnce(&mut ServiceConfig) {
     let certificate = conf
         .read_leaf_certificate()
@@ -66,6 +76,7 @@ pub fn config(
     move |cfg: &mut ServiceConfig| {
         cfg.app_data(PayloadConfig::new(10_000_000_000))
             .app_data(Data::from(Arc::clone(&snapshot_worker)))
+            .app_data(Data::from(Arc::clone(&app_keys)))
             .app_data(Data::new(conf))
             .app_data(Data::new(certificate))
             .app_data(Data::new(tpm_context))
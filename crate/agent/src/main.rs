@@ -1,10 +1,15 @@
 use anyhow::Result;
 use cosmian_vm_agent::init::initialize_agent;
-use cosmian_vm_agent::worker::snapshot;
+use cosmian_vm_agent::worker::{
+    filter::SnapshotFilter,
+    snapshot,
+    store::{SnapshotStore, SNAPSHOT_PATH},
+};
+use std::{path::Path, sync::Arc};
 
 use actix_web::middleware::Logger;
 use actix_web::{App, HttpServer};
-use cosmian_vm_agent::{conf::CosmianVmAgent, get_tls_config, CONF_PATH};
+use cosmian_vm_agent::{app::AppKeys, conf::CosmianVmAgent, get_tls_config, CONF_PATH};
 use env_logger::{Builder, Target};
 
 #[actix_web::main]
@@ -46,7 +51,15 @@ async fn main() -> Result<()> {
     // Background worker relating to the snapshot processing
     tracing::info!("Starting the snapshot worker...");
     let (snapshot_worker, snapshot_worker_handle, snapshot_worker_cancel) =
-        snapshot::init_snapshot_worker(conf.agent.tpm_device.clone());
+        snapshot::init_snapshot_worker(
+            conf.agent.tpm_device.clone(),
+            conf.tpm.clone(),
+            SnapshotFilter::try_from(conf.snapshot.clone())?,
+            SnapshotStore::new(Path::new(SNAPSHOT_PATH), &ssl_private_key),
+        );
+
+    // The ephemeral keys of the app configuration, shared by the server workers
+    let app_keys = Arc::new(AppKeys::default());
 
     // Start REST server thread
     tracing::info!("Starting Cosmian VM Agent on {host}:{port}...");
@@ -56,6 +69,7 @@ async fn main() -> Result<()> {
             .configure(cosmian_vm_agent::config(
                 conf.clone(),
                 snapshot_worker.clone(),
+                app_keys.clone(),
             ))
     })
     .bind_rustls_0_22(
@@ -0,0 +1,413 @@
+use crate::conf::{Tpm, TpmKeyAlgorithm};
+use crate::error::Error;
+
+use cosmian_vm_client::{
+    client::SealedSecret,
+    pcr::{PcrBank, PcrSelection},
+};
+use tss_esapi::{
+    abstraction::ek,
+    attributes::ObjectAttributesBuilder,
+    constants::SessionType,
+    handles::{AuthHandle, KeyHandle, ObjectHandle, PersistentTpmHandle, SessionHandle, TpmHandle},
+    interface_types::{
+        algorithm::{AsymmetricAlgorithm, HashingAlgorithm, PublicAlgorithm},
+        key_bits::RsaKeyBits,
+        resource_handles::Hierarchy,
+        session_handles::{AuthSession, PolicySession},
+    },
+    structures::{
+        Digest, EncryptedSecret, IdObject, KeyedHashScheme, PcrSelectionList,
+        PcrSelectionListBuilder, PcrSlot, Private, Public, PublicBuilder,
+        PublicKeyedHashParameters, RsaExponent, SensitiveData, SymmetricDefinition,
+        SymmetricDefinitionObject,
+    },
+    traits::{Marshall, UnMarshall},
+    utils::create_restricted_decryption_rsa_public,
+    Context,
+};
+
+impl From<TpmKeyAlgorithm> for AsymmetricAlgorithm {
+    fn from(algorithm: TpmKeyAlgorithm) -> Self {
+        match algorithm {
+            TpmKeyAlgorithm::Ecc => Self::Ecc,
+            TpmKeyAlgorithm::Rsa => Self::Rsa,
+        }
+    }
+}
+
+/// Load the key persisted at `handle`, if any
+pub(crate) fn load_persistent(
+    context: &mut Context,
+    handle: u32,
+) -> Result<Option<KeyHandle>, Error> {
+    let handle = TpmHandle::Persistent(PersistentTpmHandle::new(handle)?);
+    Ok(context.tr_from_tpm_public(handle).ok().map(KeyHandle::from))
+}
+
+/// Load the key persisted at `handle`, raising an error if there is none
+fn load_provisioned(context: &mut Context, handle: u32, name: &str) -> Result<KeyHandle, Error> {
+    load_persistent(context, handle)?
+        .ok_or_else(|| Error::Configuration(format!("No TPM {name} persisted at {handle:#010x}")))
+}
+
+/// Read the certificate (DER) of the endorsement key, provisioned in NV by the TPM manufacturer
+pub(crate) fn ek_certificate(context: &mut Context, tpm: &Tpm) -> Result<Vec<u8>, Error> {
+    ek::retrieve_ek_pubcert(context, tpm.key_algorithm.into()).map_err(|e| {
+        Error::Configuration(format!(
+            "No {} EK certificate provisioned in the TPM: {e}",
+            tpm.key_algorithm
+        ))
+    })
+}
+
+/// Recover the credential encrypted for the endorsement key and bound to the name of the
+/// attestation key (`TPM2_ActivateCredential`)
+///
+/// The TPM refuses to recover it if the attestation key whose name is bound is not loaded
+pub(crate) fn activate_credential(
+    context: &mut Context,
+    tpm: &Tpm,
+    credential_blob: Vec<u8>,
+    encrypted_secret: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let ek = load_provisioned(context, tpm.ek_handle, "EK")?;
+    let ak = load_provisioned(context, tpm.ak_handle, "AK")?;
+
+    // The EK can only be used in a policy session satisfying `PolicySecret(TPM_RH_ENDORSEMENT)`
+    let session = start_session(context, SessionType::Policy)?;
+    let credential =
+        activate_with_session(context, session, ak, ek, credential_blob, encrypted_secret);
+    context.flush_context(SessionHandle::from(session).into())?;
+
+    Ok(credential?.value().to_vec())
+}
+
+fn activate_with_session(
+    context: &mut Context,
+    session: AuthSession,
+    ak: KeyHandle,
+    ek: KeyHandle,
+    credential_blob: Vec<u8>,
+    encrypted_secret: Vec<u8>,
+) -> Result<Digest, Error> {
+    let policy_session = PolicySession::try_from(session)?;
+    context.execute_with_nullauth_session(|ctx| {
+        ctx.policy_secret(
+            policy_session,
+            AuthHandle::Endorsement,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            None,
+        )
+    })?;
+
+    context
+        .execute_with_sessions((Some(AuthSession::Password), Some(session), None), |ctx| {
+            ctx.activate_credential(
+                ak,
+                ek,
+                IdObject::try_from(credential_blob)?,
+                EncryptedSecret::try_from(encrypted_secret)?,
+            )
+        })
+        .map_err(|e| Error::BadRequest(format!("The TPM can't activate the credential: {e}")))
+}
+
+/// Seal `secret` to the current values of the PCRs `pcrs` of the bank `bank`
+///
+/// The secret is stored in an object of the storage hierarchy whose policy (`PolicyPCR`)
+/// requires the PCRs to keep these values to unseal it
+pub(crate) fn seal(
+    context: &mut Context,
+    secret: Vec<u8>,
+    pcrs: PcrSelection,
+    bank: PcrBank,
+) -> Result<SealedSecret, Error> {
+    let secret = SensitiveData::try_from(secret)
+        .map_err(|_| Error::BadRequest("The secret is too long (128 bytes max)".to_owned()))?;
+    let pcr_selection = pcr_selection(&pcrs, bank)?;
+
+    // The digest of the policy on the current PCR values, computed in a trial session
+    let trial_session = start_session(context, SessionType::Trial)?;
+    let policy_digest = policy_pcr(context, trial_session, pcr_selection)
+        .and_then(|policy_session| Ok(context.policy_get_digest(policy_session)?));
+    context.flush_context(SessionHandle::from(trial_session).into())?;
+
+    let public = PublicBuilder::new()
+        .with_public_algorithm(PublicAlgorithm::KeyedHash)
+        .with_name_hashing_algorithm(HashingAlgorithm::Sha256)
+        .with_object_attributes(
+            ObjectAttributesBuilder::new()
+                .with_fixed_tpm(true)
+                .with_fixed_parent(true)
+                .with_no_da(true)
+                .with_admin_with_policy(true)
+                .with_user_with_auth(false)
+                .build()?,
+        )
+        .with_auth_policy(policy_digest?)
+        .with_keyed_hash_parameters(PublicKeyedHashParameters::new(KeyedHashScheme::Null))
+        .with_keyed_hash_unique_identifier(Digest::default())
+        .build()?;
+
+    let storage_key = create_storage_key(context)?;
+    let sealed = context.execute_with_nullauth_session(|ctx| {
+        ctx.create(storage_key, public, None, Some(secret), None, None)
+    });
+    context.flush_context(storage_key.into())?;
+    let sealed = sealed?;
+
+    Ok(SealedSecret {
+        pcrs,
+        hash_bank: bank,
+        public: sealed.out_public.marshall()?,
+        private: sealed.out_private.value().to_vec(),
+    })
+}
+
+/// Unseal the secret `sealed`, if the PCRs still have the values they had when it was sealed
+pub(crate) fn unseal(context: &mut Context, sealed: SealedSecret) -> Result<Vec<u8>, Error> {
+    let invalid = |e| Error::BadRequest(format!("Invalid sealed secret: {e}"));
+    let public = Public::unmarshall(&sealed.public).map_err(invalid)?;
+    let private = Private::try_from(sealed.private).map_err(invalid)?;
+    let pcr_selection = pcr_selection(&sealed.pcrs, sealed.hash_bank)?;
+
+    let storage_key = create_storage_key(context)?;
+    let item = context.execute_with_nullauth_session(|ctx| ctx.load(storage_key, private, public));
+    context.flush_context(storage_key.into())?;
+    let item: ObjectHandle = item
+        .map_err(|e| Error::BadRequest(format!("The secret has not been sealed by this TPM: {e}")))?
+        .into();
+
+    let session = start_session(context, SessionType::Policy)?;
+    let secret = policy_pcr(context, session, pcr_selection).and_then(|_| {
+        context
+            .execute_with_session(Some(session), |ctx| ctx.unseal(item))
+            .map_err(|e| {
+                Error::Forbidden(format!(
+                    "The PCRs {} no longer have the values the secret was sealed to: {e}",
+                    sealed.pcrs
+                ))
+            })
+    });
+    context.flush_context(SessionHandle::from(session).into())?;
+    context.flush_context(item)?;
+
+    Ok(secret?.value().to_vec())
+}
+
+/// The storage primary key of the owner hierarchy, the parent of the sealed secrets
+///
+/// It is derived from the seed of the owner hierarchy: the same key is created each time
+fn create_storage_key(context: &mut Context) -> Result<KeyHandle, Error> {
+    let public = create_restricted_decryption_rsa_public(
+        SymmetricDefinitionObject::AES_128_CFB,
+        RsaKeyBits::Rsa2048,
+        RsaExponent::default(),
+    )?;
+
+    Ok(context
+        .execute_with_nullauth_session(|ctx| {
+            ctx.create_primary(Hierarchy::Owner, public, None, None, None, None)
+        })?
+        .key_handle)
+}
+
+/// The selection of the PCRs `pcrs` of the bank `bank`
+fn pcr_selection(pcrs: &PcrSelection, bank: PcrBank) -> Result<PcrSelectionList, Error> {
+    if pcrs.is_empty() {
+        return Err(Error::BadRequest("No PCR selected".to_owned()));
+    }
+
+    let slots = pcrs
+        .iter()
+        .map(|pcr| PcrSlot::try_from(1_u32 << pcr))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(PcrSelectionListBuilder::new()
+        .with_selection(hashing_algorithm(bank), &slots)
+        .build()?)
+}
+
+/// The hash algorithm of the PCR bank `bank`
+const fn hashing_algorithm(bank: PcrBank) -> HashingAlgorithm {
+    match bank {
+        PcrBank::Sha1 => HashingAlgorithm::Sha1,
+        PcrBank::Sha256 => HashingAlgorithm::Sha256,
+        PcrBank::Sha384 => HashingAlgorithm::Sha384,
+        PcrBank::Sha512 => HashingAlgorithm::Sha512,
+    }
+}
+
+/// Start a session of type `session_type` (policy or trial)
+fn start_session(context: &mut Context, session_type: SessionType) -> Result<AuthSession, Error> {
+    context
+        .start_auth_session(
+            None,
+            None,
+            None,
+            session_type,
+            SymmetricDefinition::AES_128_CFB,
+            HashingAlgorithm::Sha256,
+        )?
+        .ok_or_else(|| Error::Unexpected("No TPM session started".to_owned()))
+}
+
+/// Require in the policy `session` the current values of the PCRs `pcr_selection`
+fn policy_pcr(
+    context: &mut Context,
+    session: AuthSession,
+    pcr_selection: PcrSelectionList,
+) -> Result<PolicySession, Error> {
+    let policy_session = PolicySession::try_from(session)?;
+    context.policy_pcr(policy_session, Digest::default(), pcr_selection)?;
+    Ok(policy_session)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::str::FromStr;
+
+    use cosmian_vm_client::{
+        credential::{attestation_key_name, make_credential, EkPublicKey},
+        pcr::{PcrBank, PcrSelection},
+    };
+    use rsa::{BigUint, RsaPublicKey};
+    use tss_esapi::{
+        handles::{PcrHandle, PersistentTpmHandle},
+        interface_types::{
+            algorithm::HashingAlgorithm, dynamic_handles::Persistent, resource_handles::Provision,
+        },
+        structures::{Digest, DigestValues, Public},
+        traits::Marshall,
+        Context, TctiNameConf,
+    };
+
+    use super::{activate_credential, load_persistent, seal, unseal};
+    use crate::conf::{Tpm, TpmKeyAlgorithm};
+    use crate::error::Error;
+    use crate::init::tpm::generate_tpm_keys;
+
+    /// A context on the TPM simulator given by `TEST_TCTI` (ie: "swtpm:host=localhost,port=2321")
+    pub(crate) fn simulator_context() -> Context {
+        let tcti = std::env::var("TEST_TCTI").expect("TEST_TCTI is not set");
+        Context::new(TctiNameConf::from_str(&tcti).unwrap()).unwrap()
+    }
+
+    /// Remove the key persisted at `handle`, if any
+    pub(crate) fn evict(context: &mut Context, handle: u32) {
+        if let Some(key) = load_persistent(context, handle).unwrap() {
+            let persistent = Persistent::Persistent(PersistentTpmHandle::new(handle).unwrap());
+            context
+                .execute_with_nullauth_session(|ctx| {
+                    ctx.evict_control(Provision::Owner, key.into(), persistent)
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "require a swtpm simulator (TEST_TCTI)"]
+    fn test_activate_credential() {
+        let mut context = simulator_context();
+
+        for (key_algorithm, ek_handle, ak_handle) in [
+            (TpmKeyAlgorithm::Ecc, 0x8101_0111, 0x8100_0111),
+            (TpmKeyAlgorithm::Rsa, 0x8101_0112, 0x8100_0112),
+        ] {
+            evict(&mut context, ek_handle);
+            evict(&mut context, ak_handle);
+
+            let tpm = Tpm {
+                key_algorithm,
+                ek_handle,
+                ak_handle,
+                ..Tpm::default()
+            };
+            generate_tpm_keys(&mut context, &tpm).unwrap();
+
+            // The EK public key, as certified by an EK certificate
+            let ek = load_persistent(&mut context, ek_handle).unwrap().unwrap();
+            let ek = match context.read_public(ek).unwrap().0 {
+                Public::Ecc { unique, .. } => EkPublicKey::Ecc(
+                    p256::PublicKey::from_sec1_bytes(
+                        &[&[0x04], unique.x().value(), unique.y().value()].concat(),
+                    )
+                    .unwrap(),
+                ),
+                Public::Rsa { unique, .. } => EkPublicKey::Rsa(
+                    RsaPublicKey::new(BigUint::from_bytes_be(unique.value()), 65537_u32.into())
+                        .unwrap(),
+                ),
+                _ => panic!("unexpected EK type"),
+            };
+
+            // The AK name is computed from its public area, as returned with the TPM quotes
+            let ak = load_persistent(&mut context, ak_handle).unwrap().unwrap();
+            let (ak_public, ak_name, _) = context.read_public(ak).unwrap();
+            let name = attestation_key_name(&ak_public.marshall().unwrap()).unwrap();
+            assert_eq!(name, ak_name.value());
+
+            let credential = [7; 32];
+            let (credential_blob, encrypted_secret) =
+                make_credential(&ek, &name, &credential).unwrap();
+            assert_eq!(
+                activate_credential(&mut context, &tpm, credential_blob, encrypted_secret).unwrap(),
+                credential
+            );
+
+            // A credential bound to another key is not recovered
+            let mut other_name = name.clone();
+            other_name[2] ^= 0xff;
+            let (credential_blob, encrypted_secret) =
+                make_credential(&ek, &other_name, &credential).unwrap();
+            assert!(
+                activate_credential(&mut context, &tpm, credential_blob, encrypted_secret).is_err()
+            );
+
+            evict(&mut context, ek_handle);
+            evict(&mut context, ak_handle);
+        }
+    }
+
+    #[test]
+    #[ignore = "require a swtpm simulator (TEST_TCTI)"]
+    fn test_seal_unseal() {
+        let mut context = simulator_context();
+
+        // PCR 16 is the debug PCR, resettable without rebooting
+        context
+            .execute_with_nullauth_session(|ctx| ctx.pcr_reset(PcrHandle::Pcr16))
+            .unwrap();
+
+        let pcrs = PcrSelection::from_iter([16]);
+        let sealed = seal(&mut context, b"secret".to_vec(), pcrs, PcrBank::Sha256).unwrap();
+        assert_eq!(unseal(&mut context, sealed.clone()).unwrap(), b"secret");
+
+        // The secret is no longer unsealed once the PCR is extended
+        let mut digests = DigestValues::new();
+        digests.set(
+            HashingAlgorithm::Sha256,
+            Digest::try_from(vec![1; 32]).unwrap(),
+        );
+        context
+            .execute_with_nullauth_session(|ctx| ctx.pcr_extend(PcrHandle::Pcr16, digests))
+            .unwrap();
+        assert!(matches!(
+            unseal(&mut context, sealed),
+            Err(Error::Forbidden(_))
+        ));
+
+        // A secret too long to be sealed
+        assert!(matches!(
+            seal(
+                &mut context,
+                vec![0; 129],
+                PcrSelection::from_iter([16]),
+                PcrBank::Sha256
+            ),
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
@@ -1,9 +1,16 @@
 use crate::error::Error;
 
+use cosmian_vm_client::pcr::{PcrBank, PcrValues};
 use std::process::Command;
-use std::{path::Path, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tss_esapi::{Context, TctiNameConf};
 
+/// The sysfs directory of the TPM devices, exposing the PCR values of each bank
+const TPM_SYSFS_PATH: &str = "/sys/class/tpm";
+
 pub(crate) fn call(exe: &str, args: &[&str], background: bool) -> Result<Option<String>, Error> {
     if background {
         Command::new(exe).args(args).spawn()?;
@@ -36,3 +43,64 @@ pub(crate) fn create_tpm_context(tpm_device: &Path) -> Result<Context, Error> {
 
     Ok(tpm_context)
 }
+
+/// The sysfs directory of the TPM `tpm_device` (`/dev/tpmN` or its resource manager `/dev/tpmrmN`)
+fn tpm_sysfs_path(tpm_device: &Path) -> Result<PathBuf, Error> {
+    let tpm_device = std::fs::canonicalize(tpm_device).unwrap_or_else(|_| tpm_device.to_owned());
+    let name = tpm_device
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    let id = name
+        .strip_prefix("tpmrm")
+        .or_else(|| name.strip_prefix("tpm"))
+        .filter(|id| !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit()))
+        .ok_or_else(|| {
+            Error::Unexpected(format!(
+                "Can't find the sysfs directory of the TPM device {tpm_device:?} (expecting /dev/tpmN or /dev/tpmrmN)"
+            ))
+        })?;
+
+    Ok(Path::new(TPM_SYSFS_PATH).join(format!("tpm{id}")))
+}
+
+/// Read the values of the PCRs `pcrs` from the PCR bank `bank` of the TPM `tpm_device`
+///
+/// Note: require Linux 5.12+ exposing the PCR banks in sysfs
+pub(crate) fn read_pcr_values(
+    tpm_device: &Path,
+    pcrs: impl Iterator<Item = u8>,
+    bank: PcrBank,
+) -> Result<PcrValues, Error> {
+    let sysfs_path = tpm_sysfs_path(tpm_device)?;
+    pcrs.map(|pcr| {
+        let path = sysfs_path.join(format!("pcr-{bank}")).join(pcr.to_string());
+        let value = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Unexpected(format!("Can't read the PCR {pcr} at {path:?}: {e}")))?;
+        Ok((pcr, hex::encode(hex::decode(value.trim())?)))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::tpm_sysfs_path;
+
+    #[test]
+    fn test_tpm_sysfs_path() {
+        assert_eq!(
+            tpm_sysfs_path(Path::new("/nonexistent/tpmrm1")).unwrap(),
+            Path::new("/sys/class/tpm/tpm1")
+        );
+        assert_eq!(
+            tpm_sysfs_path(Path::new("/nonexistent/tpm0")).unwrap(),
+            Path::new("/sys/class/tpm/tpm0")
+        );
+        assert!(tpm_sysfs_path(Path::new("/nonexistent/tpm")).is_err());
+        assert!(tpm_sysfs_path(Path::new("/nonexistent/tpmrm0a")).is_err());
+        assert!(tpm_sysfs_path(Path::new("/nonexistent/sda")).is_err());
+    }
+}
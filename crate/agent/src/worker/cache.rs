@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fs::Metadata, os::unix::fs::MetadataExt, path::Path};
+
+use const_format::formatcp;
+use cosmian_vm_client::ser_de::base64_serde;
+use ima::ima::ImaHashMethod;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, VAR_PATH};
+
+/// The file storing the file hashes computed during the previous snapshot
+pub const HASH_CACHE_PATH: &str = formatcp!("{VAR_PATH}/snapshot.cache");
+
+/// The file metadata used to detect if a file changed since it has been hashed
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FileMetadata {
+    inode: u64,
+    size: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl FileMetadata {
+    #[must_use]
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl From<&Metadata> for FileMetadata {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            inode: metadata.ino(),
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CachedHash {
+    metadata: FileMetadata,
+    #[serde(with = "base64_serde")]
+    hash: Vec<u8>,
+}
+
+/// The hashes of the files computed during a previous snapshot indexed by path
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HashCache {
+    hash_method: ImaHashMethod,
+    entries: HashMap<String, CachedHash>,
+}
+
+impl HashCache {
+    #[must_use]
+    pub fn new(hash_method: ImaHashMethod) -> Self {
+        Self {
+            hash_method,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Load the cache stored at `path`
+    ///
+    /// Return an empty cache if the file does not exist, is unreadable
+    /// or has been built with another hash method
+    #[must_use]
+    pub fn load(path: &Path, hash_method: &ImaHashMethod) -> Self {
+        if !path.exists() {
+            return Self::new(hash_method.clone());
+        }
+
+        let cache = std::fs::read(path)
+            .map_err(Error::from)
+            .and_then(|content| Ok(serde_json::from_slice::<Self>(&content)?));
+
+        match cache {
+            Ok(cache) if &cache.hash_method == hash_method => {
+                tracing::info!("Reusing {} file hashes from {path:?}", cache.entries.len());
+                cache
+            }
+            Ok(_) => {
+                tracing::info!("Ignoring the hash cache {path:?} built with another hash method");
+                Self::new(hash_method.clone())
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring the unreadable hash cache {path:?}: {e}");
+                Self::new(hash_method.clone())
+            }
+        }
+    }
+
+    /// Get the hash of a file if its metadata didn't change since it has been hashed
+    #[must_use]
+    pub fn get(&self, path: &str, metadata: &FileMetadata) -> Option<&[u8]> {
+        self.entries
+            .get(path)
+            .filter(|cached| &cached.metadata == metadata)
+            .map(|cached| cached.hash.as_ref())
+    }
+
+    pub fn insert(&mut self, path: String, metadata: FileMetadata, hash: Vec<u8>) {
+        self.entries.insert(path, CachedHash { metadata, hash });
+    }
+
+    /// Write the cache at `path` (replacing the previous one atomically)
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ima::ima::ImaHashMethod;
+    use tempfile::TempDir;
+
+    use super::{FileMetadata, HashCache};
+
+    #[test]
+    fn test_hash_cache() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file = tmp_dir.path().join("hash_cache_file");
+        std::fs::write(&file, b"content").unwrap();
+        let metadata = FileMetadata::from(&std::fs::metadata(&file).unwrap());
+
+        let mut cache = HashCache::new(ImaHashMethod::Sha256);
+        cache.insert("/file".to_owned(), metadata.clone(), vec![1, 2, 3]);
+        assert_eq!(cache.get("/file", &metadata), Some([1, 2, 3].as_ref()));
+        assert_eq!(cache.get("/other", &metadata), None);
+
+        // the file has been modified since it has been hashed
+        std::fs::write(&file, b"new content").unwrap();
+        let new_metadata = FileMetadata::from(&std::fs::metadata(&file).unwrap());
+        assert_eq!(cache.get("/file", &new_metadata), None);
+
+        let cache_path = tmp_dir.path().join("snapshot.cache");
+        cache.save(&cache_path).unwrap();
+
+        let cache = HashCache::load(&cache_path, &ImaHashMethod::Sha256);
+        assert_eq!(cache.get("/file", &metadata), Some([1, 2, 3].as_ref()));
+
+        // a cache built with another hash method is ignored
+        let cache = HashCache::load(&cache_path, &ImaHashMethod::Sha1);
+        assert_eq!(cache.get("/file", &metadata), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Component, Path};
+
+use cosmian_vm_client::snapshot::SnapshotRules;
+use glob::{MatchOptions, Pattern};
+use walkdir::DirEntry;
+
+use crate::error::Error;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Select the files of the filesystem to hash according to the `SnapshotRules`
+#[derive(Debug)]
+pub struct SnapshotFilter {
+    rules: SnapshotRules,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl TryFrom<SnapshotRules> for SnapshotFilter {
+    type Error = Error;
+
+    fn try_from(rules: SnapshotRules) -> Result<Self, Error> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Pattern::new(pattern).map_err(|e| {
+                        Error::Configuration(format!("Invalid snapshot pattern '{pattern}': {e}"))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            include: compile(&rules.include)?,
+            exclude: compile(&rules.exclude)?,
+            rules,
+        })
+    }
+}
+
+impl SnapshotFilter {
+    #[must_use]
+    pub const fn rules(&self) -> &SnapshotRules {
+        &self.rules
+    }
+
+    /// Tell whether a directory should be traversed or a file should be hashed
+    #[must_use]
+    pub fn keep(&self, entry: &DirEntry) -> bool {
+        let path = entry.path();
+
+        if !matches_any(&self.exclude, path) || matches_any(&self.include, path) {
+            return true;
+        }
+
+        // An excluded directory is still traversed if it may contain included paths
+        entry.file_type().is_dir()
+            && self
+                .include
+                .iter()
+                .any(|pattern| may_match_below(pattern.as_str(), path))
+    }
+
+    /// Tell whether a file of that size should be hashed
+    #[must_use]
+    pub fn keep_size(&self, size: u64) -> bool {
+        self.rules
+            .max_file_size
+            .is_none_or(|max_file_size| size <= max_file_size)
+    }
+}
+
+/// Whether `path` or one of its parent directories matches one of the `patterns`
+fn matches_any(patterns: &[Pattern], path: &Path) -> bool {
+    path.ancestors().any(|path| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(path, MATCH_OPTIONS))
+    })
+}
+
+/// Whether `pattern` may match a path located inside the directory `dir`
+fn may_match_below(pattern: &str, dir: &Path) -> bool {
+    let mut pattern_components = Path::new(pattern).components();
+
+    for component in dir.components() {
+        match pattern_components.next() {
+            None => return false,
+            Some(Component::Normal(pattern_component)) => {
+                let pattern_component = pattern_component.to_string_lossy();
+                if pattern_component == "**" {
+                    return true;
+                }
+                let matched = Pattern::new(&pattern_component).is_ok_and(|pattern_component| {
+                    pattern_component.matches(&component.as_os_str().to_string_lossy())
+                });
+                if !matched {
+                    return false;
+                }
+            }
+            Some(pattern_component) => {
+                if pattern_component != component {
+                    return false;
+                }
+            }
+        }
+    }
+
+    pattern_components.next().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cosmian_vm_client::snapshot::SnapshotRules;
+
+    use super::{matches_any, may_match_below, SnapshotFilter};
+
+    #[test]
+    fn test_snapshot_filter() {
+        let filter = SnapshotFilter::try_from(SnapshotRules {
+            include: vec!["/var/lib/**/*.so".to_owned(), "/var/opt".to_owned()],
+            max_file_size: Some(1024),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let excluded = |path| matches_any(&filter.exclude, Path::new(path));
+        assert!(!excluded("/usr/bin/ls"));
+        assert!(excluded("/var/lib/app/lib.so"));
+        assert!(excluded("/var"));
+        assert!(!excluded("/variable"));
+
+        let included = |path| matches_any(&filter.include, Path::new(path));
+        assert!(included("/var/lib/app/lib.so"));
+        assert!(included("/var/opt/bin/app"));
+        assert!(!included("/var/lib/app/lib.a"));
+
+        let below = |pattern, path| may_match_below(pattern, Path::new(path));
+        assert!(below("/var/lib/**/*.so", "/var"));
+        assert!(below("/var/lib/**/*.so", "/var/lib"));
+        assert!(below("/var/lib/**/*.so", "/var/lib/app"));
+        assert!(!below("/var/lib/**/*.so", "/var/log"));
+        assert!(below("/*/opt", "/var"));
+        assert!(!below("/var/opt", "/var/opt"));
+
+        assert!(filter.keep_size(1024));
+        assert!(!filter.keep_size(1025));
+
+        assert!(SnapshotFilter::try_from(SnapshotRules {
+            exclude: vec!["/var/[".to_owned()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
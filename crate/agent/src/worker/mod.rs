@@ -1 +1,5 @@
+pub mod cache;
+pub mod filter;
+pub mod progress;
 pub mod snapshot;
+pub mod store;
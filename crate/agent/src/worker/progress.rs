@@ -0,0 +1,60 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    RwLock,
+};
+
+use cosmian_vm_client::snapshot::{SnapshotPhase, SnapshotStatus};
+
+/// The progress of the snapshot being processed
+///
+/// It's updated by the snapshot worker and can be read at any time by the route handlers
+#[derive(Debug, Default)]
+pub struct SnapshotProgress {
+    phase: RwLock<SnapshotPhase>,
+    files_total: AtomicU64,
+    files_done: AtomicU64,
+    bytes_hashed: AtomicU64,
+}
+
+impl SnapshotProgress {
+    /// Enter a new phase of the snapshot
+    ///
+    /// The counters are reset when a new snapshot is ordered
+    pub fn set_phase(&self, phase: SnapshotPhase) {
+        if phase == SnapshotPhase::Pending {
+            self.files_total.store(0, Ordering::Relaxed);
+            self.files_done.store(0, Ordering::Relaxed);
+            self.bytes_hashed.store(0, Ordering::Relaxed);
+        }
+
+        if let Ok(mut current_phase) = self.phase.write() {
+            *current_phase = phase;
+        }
+    }
+
+    pub fn set_files_total(&self, files_total: u64) {
+        self.files_total.store(files_total, Ordering::Relaxed);
+    }
+
+    /// Account a file processed by the hashing phase
+    ///
+    /// `bytes_hashed` is 0 if the hash of the file has been found in the cache
+    pub fn file_done(&self, bytes_hashed: u64) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.bytes_hashed.fetch_add(bytes_hashed, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn status(&self) -> SnapshotStatus {
+        SnapshotStatus {
+            phase: self
+                .phase
+                .read()
+                .map(|phase| phase.clone())
+                .unwrap_or(SnapshotPhase::Idle),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            bytes_hashed: self.bytes_hashed.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use futures::{future, StreamExt};
+use futures::StreamExt;
 use std::{
     path::PathBuf,
     sync::Arc,
@@ -6,24 +6,37 @@ use std::{
 };
 
 use actix_web::rt::task::JoinHandle;
-use cosmian_vm_client::snapshot::{CosmianVmSnapshot, SnapshotFiles};
-use tee_attestation::{get_quote as tee_get_quote, TeePolicy};
-use tokio::sync::Mutex;
+use cosmian_vm_client::{
+    envelope::{certificate_fingerprint, SnapshotEnvelope, SNAPSHOT_ENVELOPE_VERSION},
+    pcr::{PcrBank, PcrSelection},
+    snapshot::{CosmianVmSnapshot, SnapshotFiles, SnapshotPhase, SnapshotStatus},
+};
+use gethostname::gethostname;
+use tee_attestation::{forge_report_data_with_nonce, get_quote as tee_get_quote, TeePolicy};
+use tokio::{io::AsyncReadExt, sync::Mutex};
 use tokio_util::sync::CancellationToken;
 use tpm_quote::{get_quote as tpm_get_quote, policy::TpmPolicy};
 
 use crate::{
-    cloud_detection::which_cloud_provider, error::Error, utils::create_tpm_context,
-    DEFAULT_TPM_HASH_METHOD,
+    cloud_detection::which_cloud_provider,
+    conf::Tpm,
+    error::Error,
+    utils::{create_tpm_context, read_pcr_values},
+    worker::{
+        cache::{FileMetadata, HashCache, HASH_CACHE_PATH},
+        filter::SnapshotFilter,
+        progress::SnapshotProgress,
+        store::{SnapshotStore, StoredSnapshot},
+    },
 };
 
 use ima::ima::{read_ima_binary, Ima, ImaHashMethod};
 
-use sha1::{Digest, Sha1};
-use sha2::{Sha256, Sha512};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
 
 use std::path::Path;
-use walkdir::{DirEntry, WalkDir};
+use walkdir::WalkDir;
 
 const ROOT_PATH: &str = "/";
 
@@ -31,19 +44,70 @@ const ROOT_PATH: &str = "/";
 pub struct SnapshotJob {
     // Trigger a new snapshot
     pub trigger: bool,
+    // Rehash all the files instead of reusing the hashes of the previous snapshot
+    pub full: bool,
+    // The PCRs whose values are recorded in the snapshot
+    pub pcrs: PcrSelection,
     // The last snapshot result or None if no snapshot has been process
-    pub result: Option<Result<CosmianVmSnapshot, Error>>,
+    pub result: Option<Result<StoredSnapshot, Error>>,
+}
+
+/// The snapshot state shared between the snapshot worker and the route handlers
+#[derive(Debug)]
+pub struct Snapshot {
+    // Locked by the worker during the whole processing of a snapshot
+    job: Mutex<SnapshotJob>,
+    // Updated by the worker during the processing of a snapshot
+    progress: SnapshotProgress,
+    // Cancel the snapshot being processed (renewed for each snapshot order)
+    cancel: std::sync::Mutex<CancellationToken>,
+    // Keep the last completed snapshot across the agent restarts
+    store: SnapshotStore,
 }
 
-pub type Snapshot = Mutex<SnapshotJob>;
+impl Snapshot {
+    /// Create the snapshot state with the snapshot previously saved in `store` if any
+    fn load(store: SnapshotStore) -> Self {
+        let mut job = SnapshotJob::default();
+        let progress = SnapshotProgress::default();
+
+        match store.load() {
+            Ok(Some(stored)) => {
+                tracing::info!(
+                    "Reloading the snapshot computed at {} (UNIX time) by the agent {}",
+                    stored.created_at,
+                    stored.agent_version
+                );
+                job.result = Some(Ok(stored));
+                progress.set_phase(SnapshotPhase::Done);
+            }
+            Ok(None) => (),
+            Err(e) => tracing::warn!("Ignoring the stored snapshot: {e}"),
+        }
+
+        Self {
+            job: Mutex::new(job),
+            progress,
+            cancel: std::sync::Mutex::default(),
+            store,
+        }
+    }
+}
 
 /// Create the worker dedicated to the snapshotting of the Cosmian VM
+///
+/// The files of the filesystem to hash are selected by `filter`.
+/// The values of the PCRs configured in `tpm` are recorded unless a snapshot is ordered
+/// with other PCRs. The completed snapshot is saved into `store` and reloaded from it at startup
 #[must_use]
 pub fn init_snapshot_worker(
     tpm_device: Option<PathBuf>,
+    tpm: Tpm,
+    filter: SnapshotFilter,
+    store: SnapshotStore,
 ) -> (Arc<Snapshot>, JoinHandle<()>, CancellationToken) {
-    // construct empty snapshot
-    let snapshot = Arc::new(Snapshot::default());
+    // construct the snapshot from the previously stored one
+    let snapshot = Arc::new(Snapshot::load(store));
 
     // stop signal for snapshot worker
     let snapshot_cancel = CancellationToken::new();
@@ -55,18 +119,21 @@ pub fn init_snapshot_worker(
             snapshot,
             snapshot_cancel.clone(),
             tpm_device,
+            tpm,
+            filter,
         )),
         snapshot_cancel,
     )
 }
 
-/// Get the snapshot if it exists or None otherwise
+/// Get the snapshot (with its creation date) if it exists or None otherwise
 ///
 /// Return `Error::SnapshotIsProcessing` if the snapshot is processing
-pub(crate) fn get_snapshot(snapshot: &Snapshot) -> Result<Option<CosmianVmSnapshot>, Error> {
-    if let Ok(snapshot) = snapshot.try_lock() {
+pub(crate) fn get_snapshot(snapshot: &Snapshot) -> Result<Option<StoredSnapshot>, Error> {
+    if let Ok(snapshot) = snapshot.job.try_lock() {
         return match &snapshot.result {
             Some(Ok(result)) => Ok(Some(result.clone())),
+            Some(Err(Error::SnapshotCancelled)) => Err(Error::SnapshotCancelled),
             Some(Err(e)) => Err(Error::Unexpected(e.to_string())),
             None => Ok(None),
         };
@@ -75,15 +142,17 @@ pub(crate) fn get_snapshot(snapshot: &Snapshot) -> Result<Option<CosmianVmSnapsh
     Err(Error::SnapshotIsProcessing)
 }
 
-/// Clear the snapshot if it exists
+/// Clear the snapshot if it exists (in memory and on disk)
 ///
 /// Return `Error::SnapshotIsProcessing` if the snapshot is processing
 pub(crate) fn reset_snapshot(snapshot: &Snapshot) -> Result<(), Error> {
-    if let Ok(mut snapshot) = snapshot.try_lock() {
-        if snapshot.trigger {
+    if let Ok(mut job) = snapshot.job.try_lock() {
+        if job.trigger {
             return Err(Error::SnapshotIsProcessing);
         }
-        snapshot.result = None;
+        snapshot.store.remove()?;
+        job.result = None;
+        snapshot.progress.set_phase(SnapshotPhase::Idle);
         return Ok(());
     }
 
@@ -92,21 +161,102 @@ pub(crate) fn reset_snapshot(snapshot: &Snapshot) -> Result<(), Error> {
 
 /// Order a snapshot
 ///
+/// If `full` is set, the hashes computed by the previous snapshot are not reused.
+/// The values of the PCRs `pcrs` are recorded in the snapshot
+///
 /// Return `Error::SnapshotIsProcessing` if the snapshot is processing
-pub(crate) fn order_snapshot(snapshot: &Snapshot) -> Result<(), Error> {
-    if let Ok(mut snapshot) = snapshot.try_lock() {
-        snapshot.trigger = true;
+pub(crate) fn order_snapshot(
+    snapshot: &Snapshot,
+    full: bool,
+    pcrs: PcrSelection,
+) -> Result<(), Error> {
+    if let Ok(mut job) = snapshot.job.try_lock() {
+        job.trigger = true;
+        job.full = full;
+        job.pcrs = pcrs;
+        snapshot.progress.set_phase(SnapshotPhase::Pending);
+        if let Ok(mut cancel) = snapshot.cancel.lock() {
+            *cancel = CancellationToken::new();
+        }
         return Ok(());
     }
 
     Err(Error::SnapshotIsProcessing)
 }
 
+/// Cancel the snapshot ordered or being processed
+///
+/// Return `Error::NoSnapshotProcessing` if there is no snapshot to cancel
+pub(crate) fn cancel_snapshot(snapshot: &Snapshot) -> Result<(), Error> {
+    if let Ok(mut job) = snapshot.job.try_lock() {
+        // The worker didn't start to process the snapshot yet
+        if !job.trigger {
+            return Err(Error::NoSnapshotProcessing);
+        }
+        job.trigger = false;
+        job.result = Some(Err(Error::SnapshotCancelled));
+        snapshot.progress.set_phase(SnapshotPhase::Cancelled);
+        return Ok(());
+    }
+
+    // The worker holds the job: ask it to stop
+    snapshot
+        .cancel
+        .lock()
+        .map_err(|_| Error::Unexpected("Snapshot cancellation token poisoned".to_owned()))?
+        .cancel();
+
+    Ok(())
+}
+
+/// Wrap the snapshot into an envelope proving it has been produced by this Cosmian VM
+///
+/// The report data of the TEE quote binds the hash of the envelope body and the agent `certificate`.
+/// The hash of the envelope body is also signed by the agent TLS key
+pub(crate) fn seal_snapshot(
+    snapshot: &Snapshot,
+    stored: StoredSnapshot,
+    certificate: &[u8],
+) -> Result<SnapshotEnvelope, Error> {
+    let mut envelope = SnapshotEnvelope {
+        version: SNAPSHOT_ENVELOPE_VERSION,
+        created_at: stored.created_at,
+        agent_version: stored.agent_version,
+        hostname: gethostname().to_string_lossy().to_string(),
+        certificate: certificate.to_vec(),
+        certificate_fingerprint: certificate_fingerprint(certificate),
+        tee_quote: vec![],
+        signature: vec![],
+        snapshot: stored.snapshot,
+    };
+
+    let body_hash = envelope
+        .body_hash()
+        .map_err(|e| Error::Unexpected(e.to_string()))?;
+    envelope.signature = snapshot.store.sign(&body_hash)?;
+
+    let nonce = envelope
+        .report_data_nonce()
+        .map_err(|e| Error::Unexpected(e.to_string()))?;
+    let report_data = forge_report_data_with_nonce(&nonce, certificate)?;
+    envelope.tee_quote = tee_get_quote(Some(&report_data))?;
+
+    Ok(envelope)
+}
+
+/// Get the progress of the snapshot
+#[must_use]
+pub(crate) fn snapshot_status(snapshot: &Snapshot) -> SnapshotStatus {
+    snapshot.progress.status()
+}
+
 /// Wait for order to snapshot the Cosmian VM until `stop_signal` cancels the worker
 async fn process_snapshot_orders(
     snapshot: Arc<Snapshot>,
     stop_signal: CancellationToken,
     tpm_device: Option<PathBuf>,
+    tpm: Tpm,
+    filter: SnapshotFilter,
 ) {
     let mut interval = actix_web::rt::time::interval(Duration::from_secs(10));
 
@@ -116,14 +266,49 @@ async fn process_snapshot_orders(
                 interval.tick().await;
 
                 // only _try_ to lock so reads and writes from route handlers do not get blocked
-                if let Ok(mut snapshot) = snapshot.try_lock() {
-                    if snapshot.trigger {
+                if let Ok(mut job) = snapshot.job.try_lock() {
+                    if job.trigger {
                         tracing::info!("Processing a snapshot...");
                         let start = Instant::now();
-                        snapshot.trigger = false;
-                        snapshot.result = Some(do_snapshot(tpm_device.clone()).await);
+                        job.trigger = false;
+                        let cancel = snapshot
+                            .cancel
+                            .lock()
+                            .map(|cancel| cancel.clone())
+                            .unwrap_or_default();
+                        let result = tokio::select! {
+                            result = do_snapshot(
+                                tpm_device.clone(),
+                                &filter,
+                                &snapshot.progress,
+                                &cancel,
+                                job.full,
+                                tpm.pcrs(&job.pcrs),
+                                tpm.default_bank(),
+                            ) => result,
+                            () = cancel.cancelled() => Err(Error::SnapshotCancelled),
+                        };
+                        // A snapshot which can't be saved (e.g. the TLS key can't sign it) has failed
+                        let result = result.map(StoredSnapshot::new).and_then(|stored| {
+                            snapshot.store.save(&stored)?;
+                            Ok(stored)
+                        });
                         let duration = start.elapsed();
-                        tracing::info!("Snapshot proceed in {duration:?}");
+                        match &result {
+                            Err(Error::SnapshotCancelled) => {
+                                snapshot.progress.set_phase(SnapshotPhase::Cancelled);
+                                tracing::info!("Snapshot cancelled after {duration:?}");
+                            }
+                            Err(e) => {
+                                snapshot.progress.set_phase(SnapshotPhase::Failed(e.to_string()));
+                                tracing::error!("Snapshot failed after {duration:?}: {e}");
+                            }
+                            Ok(_) => {
+                                snapshot.progress.set_phase(SnapshotPhase::Done);
+                                tracing::info!("Snapshot proceed in {duration:?}");
+                            }
+                        }
+                        job.result = Some(result);
                     }
                 }
             }
@@ -136,34 +321,64 @@ async fn process_snapshot_orders(
 }
 
 /// Snapshot the Cosmian VM
-async fn do_snapshot(tpm_device: Option<PathBuf>) -> Result<CosmianVmSnapshot, Error> {
+///
+/// Unless `full` is set, the files whose metadata didn't change since the previous
+/// snapshot are not hashed again
+///
+/// The hashing of the filesystem stops as soon as `cancel` is cancelled
+///
+/// The values of the PCRs `pcrs` in the PCR bank `bank` are recorded, except the PCR
+/// extended by IMA which changes as soon as a file is measured
+async fn do_snapshot(
+    tpm_device: Option<PathBuf>,
+    filter: &SnapshotFilter,
+    progress: &SnapshotProgress,
+    cancel: &CancellationToken,
+    full: bool,
+    pcrs: &PcrSelection,
+    bank: PcrBank,
+) -> Result<CosmianVmSnapshot, Error> {
     // Get the measurements of the tee (the report data does not matter)
+    progress.set_phase(SnapshotPhase::TeeQuote);
     let tee_quote = tee_get_quote(None)?;
     let tee_policy = TeePolicy::try_from(tee_quote.as_ref())?;
     let cloud_type = which_cloud_provider().await;
 
     tracing::info!("Cosmian VM Agent: do_snapshot: cloud type: {cloud_type:?}");
 
-    let (filehashes, tpm_policy) = match tpm_device {
+    let (filehashes, tpm_policy, rules, skipped_files, pcr_values) = match tpm_device {
         None => {
             tracing::debug!("Cosmian VM Agent: do_snapshot: no file hash, no tpm_policy");
-            (None, None)
+            (None, None, None, None, None)
         }
         Some(tpm_device) => {
             tracing::debug!("Cosmian VM Agent: do_snapshot: tpm_device: {tpm_device:?}");
             let mut tpm_context = create_tpm_context(&tpm_device)?;
+            progress.set_phase(SnapshotPhase::TpmPolicy);
 
             // Get the policy of the tpm (the nonce and the pcr_list don't matter)
-            let (tpm_quote, _, _) =
-                tpm_get_quote(&mut tpm_context, &[], None, DEFAULT_TPM_HASH_METHOD)?;
+            let (tpm_quote, _, _) = tpm_get_quote(&mut tpm_context, &[], None, bank.into())?;
             let tpm_policy = TpmPolicy::try_from(tpm_quote.as_ref())?;
             tracing::debug!("Cosmian VM Agent: do_snapshot: tpm_quote: {tpm_quote:?}, tpm_policy: {tpm_policy:?}");
 
             // Get the IMA hashes
+            progress.set_phase(SnapshotPhase::ImaParsing);
             let ima = read_ima_binary()?;
             let ima: &[u8] = ima.as_ref();
             let ima = Ima::try_from(ima)?;
 
+            // Get the expected values of the PCRs of the boot chain
+            let pcr_values = if pcrs.is_empty() {
+                None
+            } else {
+                let ima_pcr = ima.pcr_id() as u8;
+                Some(read_pcr_values(
+                    &tpm_device,
+                    pcrs.iter().filter(|pcr| *pcr != ima_pcr),
+                    bank,
+                )?)
+            };
+
             // We use the same hash method as the one IMA used
             let hash_method = ima.hash_file_method();
             tracing::debug!("Cosmian VM Agent: do_snapshot: hash_method: {hash_method:?}");
@@ -176,10 +391,37 @@ async fn do_snapshot(tpm_device: Option<PathBuf>) -> Result<CosmianVmSnapshot, E
                     .collect(),
             );
 
+            // Reuse the hashes computed by the previous snapshot unless a full one is requested
+            let hash_cache_path = Path::new(HASH_CACHE_PATH);
+            let hash_cache = if full {
+                HashCache::new(hash_method.clone())
+            } else {
+                HashCache::load(hash_cache_path, &hash_method)
+            };
+
             // Add to the snapshotfiles all the file on the system
-            filehashes.0.extend(hash_filesystem(&hash_method).await?);
+            let (files, hash_cache, skipped_files) = hash_filesystem(
+                Path::new(ROOT_PATH),
+                &hash_method,
+                filter,
+                &hash_cache,
+                progress,
+                cancel,
+            )
+            .await?;
+            filehashes.0.extend(files);
+
+            if let Err(e) = hash_cache.save(hash_cache_path) {
+                tracing::warn!("Can't save the hash cache at {hash_cache_path:?}: {e}");
+            }
 
-            (Some(filehashes), Some(tpm_policy))
+            (
+                Some(filehashes),
+                Some(tpm_policy),
+                Some(filter.rules().clone()),
+                Some(skipped_files),
+                pcr_values,
+            )
         }
     };
 
@@ -188,76 +430,211 @@ async fn do_snapshot(tpm_device: Option<PathBuf>) -> Result<CosmianVmSnapshot, E
         tee_policy,
         tpm_policy,
         filehashes,
+        rules,
+        skipped_files,
+        pcr_bank: pcr_values.as_ref().map(|_| bank),
+        pcr_values,
     })
 }
 
+/// Size of the buffer used to read a file to hash
+///
+/// The memory used to hash the files is bounded by this size times the number of concurrent hashes
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
 #[inline(always)]
 pub(crate) async fn hash_file(path: &Path, hash_method: &ImaHashMethod) -> Result<Vec<u8>, Error> {
     match hash_method {
-        ImaHashMethod::Sha1 => {
-            let mut hasher = Sha1::new();
-            hasher.update(tokio::fs::read(path).await?);
-            Ok(hasher.finalize().to_vec())
-        }
-        ImaHashMethod::Sha256 => {
-            let mut hasher = Sha256::new();
-            hasher.update(tokio::fs::read(path).await?);
-            Ok(hasher.finalize().to_vec())
-        }
-        ImaHashMethod::Sha512 => {
-            let mut hasher = Sha512::new();
-            hasher.update(tokio::fs::read(path).await?);
-            Ok(hasher.finalize().to_vec())
+        ImaHashMethod::Sha1 => hash_file_with::<Sha1>(path).await,
+        ImaHashMethod::Sha256 => hash_file_with::<Sha256>(path).await,
+        ImaHashMethod::Sha512 => hash_file_with::<Sha512>(path).await,
+    }
+}
+
+/// Hash a file chunk by chunk to avoid loading it entirely in memory
+async fn hash_file_with<D: Digest>(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = usize::try_from(file.metadata().await?.len()).unwrap_or(HASH_BUFFER_SIZE);
+    let mut buffer = vec![0; size.clamp(1, HASH_BUFFER_SIZE)];
+
+    let mut hasher = D::new();
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
         }
+        hasher.update(&buffer[..read]);
     }
+
+    Ok(hasher.finalize().to_vec())
 }
 
+/// Hash all the files below `root` selected by `filter`
+///
+/// The hash found in `hash_cache` is reused for a file which metadata didn't change.
+/// The advancement is reported into `progress`.
+/// Return `Error::SnapshotCancelled` if `cancel` is cancelled meanwhile.
+/// Return the hashes of the files, the cache to use for the next snapshot
+/// and the number of entries skipped because they can't be listed or read
 #[inline(always)]
-pub async fn hash_filesystem(hash_method: &ImaHashMethod) -> Result<Vec<(String, Vec<u8>)>, Error> {
+pub async fn hash_filesystem(
+    root: &Path,
+    hash_method: &ImaHashMethod,
+    filter: &SnapshotFilter,
+    hash_cache: &HashCache,
+    progress: &SnapshotProgress,
+    cancel: &CancellationToken,
+) -> Result<(Vec<(String, Vec<u8>)>, HashCache, u64), Error> {
+    progress.set_phase(SnapshotPhase::FileListing);
+
     // Collect the files first
     // We store all the files in memory. It's a tradeoff to then quickly hash in parallel all the files
     // Listing the files is pretty quick: negligible against hashing the files
-    let files: Vec<_> = WalkDir::new(ROOT_PATH)
+    let rules = filter.rules();
+    let mut files = Vec::new();
+    let mut skipped_files = 0;
+    for entry in WalkDir::new(root)
+        .follow_links(rules.follow_symlinks)
+        .same_file_system(!rules.cross_filesystems)
         .into_iter()
-        .filter_entry(filter_whilelist)
-        .filter_map(std::result::Result::ok)
-        // Only keeps files
-        .filter(|file| file.file_type().is_file())
-        .map(walkdir::DirEntry::into_path)
-        .collect();
+        .filter_entry(|entry| filter.keep(entry))
+    {
+        // Listing the files is blocking: check the cancellation between each entry
+        if cancel.is_cancelled() {
+            return Err(Error::SnapshotCancelled);
+        }
+
+        // An unreadable directory or a broken symlink is reported as skipped
+        let metadata = entry.and_then(|file| {
+            let metadata = file.file_type().is_file().then(|| file.metadata());
+            Ok((file, metadata.transpose()?))
+        });
+        match metadata {
+            // Only keeps files
+            Ok((file, Some(metadata))) => {
+                if filter.keep_size(metadata.len()) {
+                    files.push((file.into_path(), FileMetadata::from(&metadata)));
+                }
+            }
+            Ok((_, None)) => (),
+            Err(e) => {
+                tracing::debug!("Cosmian VM Agent: hash_filesystem: skip entry: {e}");
+                skipped_files += 1;
+            }
+        }
+    }
+
+    progress.set_files_total(files.len() as u64);
+    progress.set_phase(SnapshotPhase::Hashing);
 
     // Create threads to compute the hash in parallel
     // Note: processing like that doesn't block the main thread when stopping
-    Ok(futures::stream::iter(files.into_iter())
-        .map(|file| {
+    let hashes = futures::stream::iter(files)
+        .map(|(file, metadata)| {
             async move {
-                hash_file(&file, hash_method)
-                    .await
-                    .ok() // We ignore file if the hashing fails
-                    .map(|hash| (file.display().to_string(), hash))
+                let path = file.display().to_string();
+                if let Some(hash) = hash_cache.get(&path, &metadata) {
+                    progress.file_done(0);
+                    return Ok((path, metadata, hash.to_vec()));
+                }
+
+                let hash = hash_file(&file, hash_method).await;
+                progress.file_done(if hash.is_ok() { metadata.size() } else { 0 });
+                match hash {
+                    Ok(hash) => Ok((path, metadata, hash)),
+                    Err(e) => {
+                        // We ignore file if the hashing fails
+                        tracing::debug!("Cosmian VM Agent: hash_filesystem: skip {path}: {e}");
+                        Err(path)
+                    }
+                }
             }
         })
         .buffer_unordered(num_cpus::get()) // Run up to X concurrently
-        .filter_map(future::ready)
+        .take_until(cancel.cancelled())
         .collect::<Vec<_>>()
-        .await)
+        .await;
+
+    if cancel.is_cancelled() {
+        return Err(Error::SnapshotCancelled);
+    }
+
+    let mut new_hash_cache = HashCache::new(hash_method.clone());
+    let hashes = hashes
+        .into_iter()
+        .filter_map(|hash| match hash {
+            Ok((path, metadata, hash)) => {
+                new_hash_cache.insert(path.clone(), metadata, hash.clone());
+                Some((path, hash))
+            }
+            Err(_) => {
+                skipped_files += 1;
+                None
+            }
+        })
+        .collect();
+
+    if skipped_files > 0 {
+        tracing::warn!(
+            "{skipped_files} files or directories can't be read and are missing from the snapshot"
+        );
+    }
+
+    Ok((hashes, new_hash_cache, skipped_files))
 }
 
-const BASE_EXCLUDE_DIRS: [&str; 8] = [
-    "/sys/",
-    "/run/",
-    "/proc/",
-    "/lost+found/",
-    "/dev/",
-    "/media/",
-    "/var/",
-    "/tmp/",
-];
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::{symlink, PermissionsExt},
+    };
 
-#[must_use]
-pub fn filter_whilelist(entry: &DirEntry) -> bool {
-    // Do not keep files in some folders
-    !BASE_EXCLUDE_DIRS
-        .iter()
-        .any(|exclude_dir| entry.path().starts_with(exclude_dir))
+    use cosmian_vm_client::snapshot::SnapshotRules;
+    use ima::ima::ImaHashMethod;
+    use tokio_util::sync::CancellationToken;
+
+    use super::hash_filesystem;
+    use crate::worker::{cache::HashCache, filter::SnapshotFilter, progress::SnapshotProgress};
+
+    #[actix_web::test]
+    async fn test_hash_filesystem_skipped_files() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("app"), b"app").unwrap();
+        symlink(root.path().join("missing"), root.path().join("broken")).unwrap();
+        let locked = root.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("secret"), b"secret").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // The permissions are bypassed when running as root
+        let locked_readable = fs::read_dir(&locked).is_ok();
+
+        let filter = SnapshotFilter::try_from(SnapshotRules {
+            exclude: vec![],
+            follow_symlinks: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let result = hash_filesystem(
+            root.path(),
+            &ImaHashMethod::Sha256,
+            &filter,
+            &HashCache::new(ImaHashMethod::Sha256),
+            &SnapshotProgress::default(),
+            &CancellationToken::new(),
+        )
+        .await;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (hashes, _, skipped_files) = result.unwrap();
+        let paths: Vec<_> = hashes.iter().map(|(path, _)| path.as_str()).collect();
+        assert!(paths.contains(&root.path().join("app").to_str().unwrap()));
+        if locked_readable {
+            assert_eq!(hashes.len(), 2);
+            assert_eq!(skipped_files, 1);
+        } else {
+            assert_eq!(hashes.len(), 1);
+            assert_eq!(skipped_files, 2);
+        }
+    }
 }
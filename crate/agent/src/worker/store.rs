@@ -0,0 +1,207 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use const_format::formatcp;
+use cosmian_vm_client::snapshot::CosmianVmSnapshot;
+use p256::{
+    ecdsa::{
+        signature::{Signer, Verifier},
+        Signature, SigningKey,
+    },
+    pkcs8::DecodePrivateKey,
+    SecretKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::{error::Error, VAR_PATH};
+
+/// The file storing the last completed snapshot
+pub const SNAPSHOT_PATH: &str = formatcp!("{VAR_PATH}/snapshot.json");
+
+/// The snapshot stored on disk to survive the agent restarts
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredSnapshot {
+    /// The date of the snapshot (seconds since the UNIX epoch)
+    pub created_at: u64,
+    /// The version of the agent which computed the snapshot
+    pub agent_version: String,
+    pub snapshot: CosmianVmSnapshot,
+}
+
+impl StoredSnapshot {
+    #[must_use]
+    pub fn new(snapshot: CosmianVmSnapshot) -> Self {
+        Self {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            agent_version: env!("CARGO_PKG_VERSION").to_owned(),
+            snapshot,
+        }
+    }
+}
+
+/// The content of the store file: the JSON content and its signature (hex encoded DER)
+#[derive(Deserialize, Serialize)]
+struct SignedContent<'a> {
+    signature: String,
+    #[serde(borrow)]
+    content: &'a RawValue,
+}
+
+/// Store the snapshot on disk with its signature by the agent TLS key
+///
+/// The signature prevents the agent from reloading a snapshot modified on disk
+#[derive(Debug)]
+pub struct SnapshotStore {
+    path: PathBuf,
+    private_key: PathBuf,
+}
+
+impl SnapshotStore {
+    /// Create a store signing the snapshot with the P-256 key read from `private_key` (PEM)
+    #[must_use]
+    pub fn new(path: &Path, private_key: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            private_key: private_key.to_owned(),
+        }
+    }
+
+    /// Load the stored snapshot if any
+    pub fn load(&self) -> Result<Option<StoredSnapshot>, Error> {
+        self.read_signed()?
+            .map(|content| Ok(serde_json::from_slice(&content)?))
+            .transpose()
+    }
+
+    pub fn save(&self, snapshot: &StoredSnapshot) -> Result<(), Error> {
+        self.write_signed(&serde_json::to_vec(snapshot)?)
+    }
+
+    /// Remove the stored snapshot if any
+    pub fn remove(&self) -> Result<(), Error> {
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn signing_key(&self) -> Result<SigningKey, Error> {
+        let pem = std::fs::read_to_string(&self.private_key)?;
+        SigningKey::from_pkcs8_pem(&pem)
+            .or_else(|_| SecretKey::from_sec1_pem(&pem).map(SigningKey::from))
+            .map_err(|_| {
+                Error::Cryptography(format!(
+                    "The private key {:?} is not a P-256 key: can't sign the snapshot",
+                    self.private_key
+                ))
+            })
+    }
+
+    /// Sign `message` with the agent TLS key (DER encoded ECDSA signature)
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let signature: Signature = self.signing_key()?.sign(message);
+        Ok(signature.to_der().to_bytes().into_vec())
+    }
+
+    /// Replace the stored content (JSON) by `content` signed
+    ///
+    /// The content is written into a temporary file renamed afterwards,
+    /// so the previous content is kept if the agent stops meanwhile
+    fn write_signed(&self, content: &[u8]) -> Result<(), Error> {
+        let signed = serde_json::to_vec(&SignedContent {
+            signature: hex::encode(self.sign(content)?),
+            content: serde_json::from_slice(content)?,
+        })?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&signed)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn read_signed(&self) -> Result<Option<Vec<u8>>, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let signed = std::fs::read(&self.path)?;
+        let signed: SignedContent = serde_json::from_slice(&signed)?;
+        let content = signed.content.get().as_bytes();
+        let signature = Signature::from_der(&hex::decode(&signed.signature)?)
+            .map_err(|e| Error::Cryptography(format!("Malformed snapshot signature: {e}")))?;
+
+        self.signing_key()?
+            .verifying_key()
+            .verify(content, &signature)
+            .map_err(|_| {
+                Error::Cryptography(format!(
+                    "The signature of the snapshot {:?} is invalid",
+                    self.path
+                ))
+            })?;
+
+        Ok(Some(content.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::{pkcs8::EncodePrivateKey, SecretKey};
+    use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+    use tempfile::TempDir;
+
+    use super::SnapshotStore;
+
+    #[test]
+    fn test_snapshot_store() {
+        let tmp_dir = TempDir::new().unwrap();
+        let private_key = tmp_dir.path().join("snapshot_store.key");
+        let secret_key = SecretKey::random(&mut ChaCha20Rng::from_entropy());
+        std::fs::write(
+            &private_key,
+            secret_key
+                .to_pkcs8_pem(der::pem::LineEnding::LF)
+                .unwrap()
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let store = SnapshotStore::new(&tmp_dir.path().join("snapshot_store.json"), &private_key);
+        store.remove().unwrap();
+        assert_eq!(store.read_signed().unwrap(), None);
+
+        store.write_signed(br#"{"snapshot":1}"#).unwrap();
+        assert_eq!(
+            store.read_signed().unwrap(),
+            Some(br#"{"snapshot":1}"#.to_vec())
+        );
+
+        // the snapshot is replaced
+        store.write_signed(br#"{"snapshot":2}"#).unwrap();
+        assert_eq!(
+            store.read_signed().unwrap(),
+            Some(br#"{"snapshot":2}"#.to_vec())
+        );
+        assert!(!store.path.with_extension("tmp").exists());
+
+        // the snapshot has been modified on disk
+        let modified = std::fs::read_to_string(&store.path)
+            .unwrap()
+            .replace(r#"{"snapshot":2}"#, r#"{"snapshot":3}"#);
+        std::fs::write(&store.path, modified).unwrap();
+        assert!(store.read_signed().is_err());
+
+        store.remove().unwrap();
+        assert_eq!(store.read_signed().unwrap(), None);
+    }
+}
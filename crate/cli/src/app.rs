@@ -2,7 +2,18 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use clap::{Args, Subcommand};
-use cosmian_vm_client::client::CosmianVmClient;
+use cosmian_vm_client::{
+    app_conf,
+    client::{app_key_binding_nonce, CosmianVmClient},
+    cloud_provider::CloudProvider,
+};
+use rand::RngCore;
+
+use crate::verify::{
+    load_tpm_ek_ca,
+    report::{ReportFormat, VerifyReport},
+    verify_bound_tee_quote, ImaState, Reference, Verification,
+};
 
 #[derive(Subcommand)]
 pub enum AppConfArgs {
@@ -11,21 +22,85 @@ pub enum AppConfArgs {
 }
 
 /// Init the deployed application by providing the conf
+///
+/// The Cosmian VM is verified first, then the conf is encrypted for an ephemeral key
+/// of the agent bound to a fresh TEE quote: only the verified VM can decrypt it
 #[derive(Args, Debug)]
 pub struct InitArgs {
     /// Path of the app configuration to upload
     #[arg(short, long)]
     configuration: PathBuf,
+
+    /// Path of the Cosmian VM snapshot
+    ///
+    /// Repeat it to verify against the union of several snapshots of VMs created from the same image
+    #[arg(short, long, required = true)]
+    snapshot: Vec<PathBuf>,
+
+    /// Path of the whitelist (TOML) of the files allowed even if they are not in the snapshot
+    #[arg(short, long)]
+    whitelist: Option<PathBuf>,
+
+    /// Path of the TPM manufacturer CA certificates (PEM) the EK certificate must be issued by
+    #[arg(long)]
+    tpm_ek_ca: Option<PathBuf>,
+
+    /// On Azure, encrypt the conf for the key of the agent even though it can't be bound
+    /// to a TEE quote (the report data can't be set): only the TLS connection to the verified
+    /// agent protects it
+    #[arg(long)]
+    allow_unbound_key: bool,
 }
 
 impl InitArgs {
     pub async fn run(&self, client: &CosmianVmClient) -> Result<()> {
-        println!("Processing the init of the deployed app...");
-
         let cfg_content = std::fs::read(&self.configuration)
             .map_err(|e| anyhow::anyhow!("Cannot find conf file {:?}: {e}", self.configuration))?;
 
-        client.init_app(&cfg_content).await?;
+        println!("Verifying the Cosmian VM...");
+        let reference = Reference::load(&self.snapshot, self.whitelist.as_deref())?;
+        let tpm_ek_ca = self.tpm_ek_ca.as_deref().map(load_tpm_ek_ca).transpose()?;
+
+        let mut report = VerifyReport::new(&[0; 32], ReportFormat::Text);
+        Verification {
+            reference: &reference,
+            applications: &[],
+            keep_going: false,
+            tpm_ek_ca: tpm_ek_ca.as_deref(),
+        }
+        .run(client, &mut report, &mut ImaState::default())
+        .await;
+        report.finish()?;
+
+        println!("Processing the init of the deployed app...");
+
+        // The key of the agent must be bound to a fresh TEE quote of the verified VM
+        let mut nonce = [0; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let app_key = client.app_key(&nonce).await?;
+        if reference.snapshot.cloud_type == Some(CloudProvider::Azure) {
+            if !self.allow_unbound_key {
+                anyhow::bail!("On Azure, the app key of the agent can't be bound to a TEE quote: use --allow-unbound-key to trust the verified agent anyway");
+            }
+            println!("[ WARNING ] The app key of the agent is not bound to a TEE quote on Azure");
+        } else {
+            verify_bound_tee_quote(
+                app_key.tee_quote,
+                &reference.snapshot,
+                &app_key_binding_nonce(&nonce, &app_key.public_key),
+                &client.certificate.0,
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("The app key of the agent can't be trusted: {e}"))?;
+        }
+
+        client
+            .init_app(&app_conf::encrypt(
+                &nonce,
+                &app_key.public_key,
+                &cfg_content,
+            )?)
+            .await?;
 
         println!("The app has been configured and started");
 
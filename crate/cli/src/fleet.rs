@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use cosmian_vm_client::client::CosmianVmClient;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use x509_cert::Certificate;
+
+use crate::verify::{
+    load_tpm_ek_ca,
+    report::{CheckKind, CheckStatus, ReportFormat, VerifyReport},
+    ImaState, Reference, Verification,
+};
+
+#[derive(Subcommand)]
+pub enum FleetArgs {
+    Verify(FleetVerifyArgs),
+}
+
+/// The Cosmian VMs of a fleet (TOML)
+///
+/// ```toml
+/// [[vm]]
+/// name = "vm1"
+/// url = "https://vm1.my_app.dev:5555"
+/// snapshots = ["vm1.snapshot"]
+/// whitelist = "whitelist.toml"
+/// applications = ["vm1.my_app.dev:443"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Inventory {
+    #[serde(default)]
+    vm: Vec<InventoryVm>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InventoryVm {
+    /// Name displayed in the report (default to the url)
+    name: Option<String>,
+    /// URL of the Cosmian VM Agent
+    url: String,
+    /// Paths of the snapshots, relative to the inventory file
+    snapshots: Vec<PathBuf>,
+    /// Path of the whitelist, relative to the inventory file
+    whitelist: Option<PathBuf>,
+    /// Application urls to verify against Cosmian VM TLS certificate
+    #[serde(default)]
+    applications: Vec<String>,
+}
+
+/// Verify all the Cosmian VMs of an inventory
+#[derive(Args, Debug)]
+pub struct FleetVerifyArgs {
+    /// Path of the inventory (TOML) giving the snapshots, whitelist and applications of each VM
+    #[arg(short, long)]
+    inventory: PathBuf,
+
+    /// Maximum number of VMs verified concurrently
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Run all the checks of a VM even if one fails
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Path of the JSON report of the fleet
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Path of the TPM manufacturer CA certificates (PEM) the EK certificates must be issued by
+    #[arg(long)]
+    tpm_ek_ca: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FleetVmStatus {
+    Ok,
+    /// A check failed
+    Fail,
+    /// The VM can't be verified (unreachable agent, invalid snapshot...)
+    Error,
+}
+
+/// The verification of a VM of the fleet
+#[derive(Debug, Serialize)]
+struct FleetVmReport {
+    name: String,
+    url: String,
+    status: FleetVmStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<VerifyReport>,
+}
+
+impl FleetVmReport {
+    /// The status of the checks of the class `kind`
+    fn check_status(&self, kind: CheckKind) -> &'static str {
+        let Some(report) = &self.report else {
+            return "-";
+        };
+        let statuses = report
+            .checks
+            .iter()
+            .filter(|check| check.kind == kind)
+            .map(|check| check.status)
+            .collect::<Vec<_>>();
+
+        if statuses.is_empty() {
+            "-"
+        } else if statuses.contains(&CheckStatus::Fail) {
+            "FAIL"
+        } else if statuses.iter().all(|status| *status == CheckStatus::Skip) {
+            "SKIP"
+        } else {
+            "OK"
+        }
+    }
+}
+
+impl FleetArgs {
+    pub async fn run(&self, allow_insecure_tls: bool) -> Result<()> {
+        match self {
+            Self::Verify(args) => args.run(allow_insecure_tls).await,
+        }
+    }
+}
+
+impl FleetVerifyArgs {
+    pub async fn run(&self, allow_insecure_tls: bool) -> Result<()> {
+        let inventory: Inventory = toml::from_str(&fs::read_to_string(&self.inventory)?)
+            .map_err(|e| anyhow::anyhow!("Invalid inventory {:?}: {e}", self.inventory))?;
+        if inventory.vm.is_empty() {
+            anyhow::bail!("No VM in the inventory {:?}", self.inventory);
+        }
+
+        let base = self.inventory.parent().unwrap_or_else(|| Path::new("."));
+        let tpm_ek_ca = self.tpm_ek_ca.as_deref().map(load_tpm_ek_ca).transpose()?;
+
+        println!("Verifying {} VMs...", inventory.vm.len());
+
+        let reports = stream::iter(inventory.vm)
+            .map(|vm| self.verify(vm, base, tpm_ek_ca.as_deref(), allow_insecure_tls))
+            .buffered(self.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        print_table(&reports);
+
+        if let Some(path) = &self.report {
+            fs::write(path, serde_json::to_vec_pretty(&reports)?)?;
+            println!("The report has been saved at {}", path.display());
+        }
+
+        let failed = reports
+            .iter()
+            .filter(|report| report.status != FleetVmStatus::Ok)
+            .count();
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} VMs failed the verification", reports.len());
+        }
+
+        Ok(())
+    }
+
+    async fn verify(
+        &self,
+        vm: InventoryVm,
+        base: &Path,
+        tpm_ek_ca: Option<&[Certificate]>,
+        allow_insecure_tls: bool,
+    ) -> FleetVmReport {
+        let name = vm.name.clone().unwrap_or_else(|| vm.url.clone());
+
+        let result = async {
+            let reference = Reference::load(
+                &vm.snapshots
+                    .iter()
+                    .map(|snapshot| base.join(snapshot))
+                    .collect::<Vec<_>>(),
+                vm.whitelist
+                    .map(|whitelist| base.join(whitelist))
+                    .as_deref(),
+            )?;
+
+            // Getting the agent certificate is blocking
+            let url = vm.url.clone();
+            let client = spawn_blocking(move || {
+                CosmianVmClient::instantiate(&url, env!("CARGO_PKG_VERSION"), allow_insecure_tls)
+            })
+            .await??;
+
+            let mut report = VerifyReport::new(&[0; 32], ReportFormat::Json);
+            Verification {
+                reference: &reference,
+                applications: &vm.applications,
+                keep_going: self.keep_going,
+                tpm_ek_ca,
+            }
+            .run(&client, &mut report, &mut ImaState::default())
+            .await;
+            report.close();
+
+            Ok::<_, anyhow::Error>(report)
+        }
+        .await;
+
+        match result {
+            Ok(report) => FleetVmReport {
+                name,
+                url: vm.url,
+                status: if report.has_failed() {
+                    FleetVmStatus::Fail
+                } else {
+                    FleetVmStatus::Ok
+                },
+                error: None,
+                report: Some(report),
+            },
+            Err(e) => FleetVmReport {
+                name,
+                url: vm.url,
+                status: FleetVmStatus::Error,
+                error: Some(format!("{e:#}")),
+                report: None,
+            },
+        }
+    }
+}
+
+/// Print a line per VM with the status of each class of checks
+fn print_table(reports: &[FleetVmReport]) {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("NAME".len());
+
+    println!(
+        "{:<width$}  {:<5} {:<5} {:<5} {:<5} {:<5} {:<5} RESULT",
+        "NAME", "IMA", "TPM", "BOOT", "EK", "TEE", "APPS"
+    );
+    for report in reports {
+        let result = match report.status {
+            FleetVmStatus::Ok => "OK".to_owned(),
+            FleetVmStatus::Fail => format!(
+                "FAIL ({})",
+                report
+                    .report
+                    .as_ref()
+                    .and_then(VerifyReport::first_failure)
+                    .map(|kind| kind.to_string())
+                    .unwrap_or_default()
+            ),
+            FleetVmStatus::Error => {
+                format!("ERROR ({})", report.error.as_deref().unwrap_or_default())
+            }
+        };
+        println!(
+            "{:<width$}  {:<5} {:<5} {:<5} {:<5} {:<5} {:<5} {result}",
+            report.name,
+            report.check_status(CheckKind::ImaIntegrity),
+            report.check_status(CheckKind::TpmAttestation),
+            report.check_status(CheckKind::BootChain),
+            report.check_status(CheckKind::TpmEndorsement),
+            report.check_status(CheckKind::TeeAttestation),
+            report.check_status(CheckKind::ApplicationTls),
+        );
+    }
+}
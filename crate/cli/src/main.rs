@@ -3,15 +3,19 @@ use anyhow::Result;
 use clap::{Parser, Subcommand};
 
 pub mod app;
+pub mod fleet;
 pub mod log_init;
 pub mod snapshot;
 pub mod verify;
+pub mod watch;
 
 use app::AppConfArgs;
 use cosmian_vm_client::client::CosmianVmClient;
+use fleet::FleetArgs;
 use log_init::log_init;
 use snapshot::SnapshotArgs;
-use verify::VerifyArgs;
+use verify::{report::VerifyError, VerifyArgs};
+use watch::WatchArgs;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -19,9 +23,9 @@ struct Cli {
     #[command(subcommand)]
     command: CliCommands,
 
-    /// The URL of the Cosmian VM
+    /// The URL of the Cosmian VM (not required by the commands working offline)
     #[arg(long, action)]
-    url: String,
+    url: Option<String>,
 
     /// Allow to connect using a self signed cert or not trusted cert chain
     #[arg(long)]
@@ -32,6 +36,9 @@ struct Cli {
 enum CliCommands {
     Snapshot(SnapshotArgs),
     Verify(VerifyArgs),
+    Watch(WatchArgs),
+    #[command(subcommand)]
+    Fleet(FleetArgs),
     #[command(subcommand)]
     App(AppConfArgs),
 }
@@ -43,20 +50,39 @@ async fn main() -> Result<()> {
 
     let opts = Cli::parse();
 
-    let client = CosmianVmClient::instantiate(
-        &opts.url,
-        env!("CARGO_PKG_VERSION"),
-        opts.allow_insecure_tls,
-    )?;
+    // `watch` and `fleet` instantiate their own clients for each agent
+    let client = opts
+        .url
+        .as_deref()
+        .filter(|_| !matches!(opts.command, CliCommands::Watch(_) | CliCommands::Fleet(_)))
+        .map(|url| {
+            CosmianVmClient::instantiate(url, env!("CARGO_PKG_VERSION"), opts.allow_insecure_tls)
+        })
+        .transpose()?;
 
-    match opts.command {
-        CliCommands::Snapshot(args) => args.run(&client).await,
-        CliCommands::Verify(args) => args.run(&client).await,
+    let result = match opts.command {
+        CliCommands::Snapshot(args) => args.run(client.as_ref()).await,
+        CliCommands::Verify(args) => args.run(required(client.as_ref())?).await,
+        CliCommands::Watch(args) => args.run(opts.url.as_deref(), opts.allow_insecure_tls).await,
+        CliCommands::Fleet(args) => args.run(opts.allow_insecure_tls).await,
         CliCommands::App(args) => match args {
-            AppConfArgs::Init(args) => args.run(&client).await,
-            AppConfArgs::Restart(args) => args.run(&client).await,
+            AppConfArgs::Init(args) => args.run(required(client.as_ref())?).await,
+            AppConfArgs::Restart(args) => args.run(required(client.as_ref())?).await,
         },
-    }?;
+    };
+
+    // A failed verification exits with the code of the class of the failed check
+    if let Err(e) = &result {
+        if let Some(error) = e.downcast_ref::<VerifyError>() {
+            eprintln!("Error: {error}");
+            std::process::exit(error.kind.exit_code());
+        }
+    }
+
+    result
+}
 
-    Ok(())
+/// Get the client of the Cosmian VM for the commands requiring one
+fn required(client: Option<&CosmianVmClient>) -> Result<&CosmianVmClient> {
+    client.ok_or_else(|| anyhow::anyhow!("The argument --url is required by this command"))
 }
@@ -0,0 +1,38 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use cosmian_vm_client::envelope::{SnapshotFile, SnapshotFormat};
+
+/// Convert a snapshot file between the JSON and the binary formats
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Path of the snapshot to convert (the format is auto-detected)
+    snapshot: PathBuf,
+
+    /// Path to save the converted snapshot
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Format of the converted snapshot: `json` or `binary`
+    #[arg(long)]
+    format: SnapshotFormat,
+}
+
+impl ConvertArgs {
+    pub fn run(&self) -> Result<()> {
+        let content = fs::read(&self.snapshot)?;
+        let from = SnapshotFormat::detect(&content);
+        let snapshot = SnapshotFile::from_bytes(&content)?;
+
+        fs::write(&self.output, snapshot.to_bytes(self.format)?)?;
+
+        println!(
+            "The snapshot has been converted from {from} to {} at: {}",
+            self.format,
+            self.output.to_string_lossy()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use cosmian_vm_client::{
+    diff::{Change, SnapshotDiff},
+    envelope::SnapshotFile,
+};
+use serde::Serialize;
+
+/// Show the differences between two snapshots
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Path of the reference snapshot
+    old: PathBuf,
+
+    /// Path of the snapshot to compare with the reference
+    new: PathBuf,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+impl DiffArgs {
+    pub fn run(&self) -> Result<()> {
+        let old = SnapshotFile::from_bytes(&fs::read(&self.old)?)?.into_snapshot();
+        let new = SnapshotFile::from_bytes(&fs::read(&self.new)?)?.into_snapshot();
+
+        let diff = old.diff(&new);
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else {
+            print_diff(&diff)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn print_diff(diff: &SnapshotDiff) -> Result<()> {
+    if diff.is_empty() {
+        println!("The snapshots are identical");
+        return Ok(());
+    }
+
+    print_change("cloud_type", diff.cloud_type.as_ref())?;
+    print_change("tee_policy", diff.tee_policy.as_ref())?;
+    print_change("tpm_policy", diff.tpm_policy.as_ref())?;
+    print_change("pcr_values", diff.pcr_values.as_ref())?;
+
+    for file in &diff.files.added {
+        println!("+ {} {}", file.path, file.hashes.join(","));
+    }
+    for file in &diff.files.removed {
+        println!("- {} {}", file.path, file.hashes.join(","));
+    }
+    for file in &diff.files.changed {
+        println!(
+            "~ {} {} -> {}",
+            file.path,
+            file.old_hashes.join(","),
+            file.new_hashes.join(",")
+        );
+    }
+
+    println!(
+        "{} files added, {} removed, {} changed",
+        diff.files.added.len(),
+        diff.files.removed.len(),
+        diff.files.changed.len()
+    );
+
+    Ok(())
+}
+
+fn print_change<T: Serialize>(name: &str, change: Option<&Change<T>>) -> Result<()> {
+    if let Some(change) = change {
+        println!("{name} differs:");
+        println!("- {}", serde_json::to_string(&change.old)?);
+        println!("+ {}", serde_json::to_string(&change.new)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use cosmian_vm_client::{
+    envelope::{SnapshotFile, SnapshotFormat},
+    merge::merge_snapshots,
+};
+
+/// Merge the snapshots of several VMs created from the same image into a reference snapshot
+///
+/// The merged snapshot is not produced by an agent: it is saved without any envelope,
+/// so `snapshot verify-file` rejects it. Verify the merged snapshots with `verify-file` first
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Paths of the snapshots to merge
+    #[arg(required = true, num_args = 2..)]
+    snapshots: Vec<PathBuf>,
+
+    /// Path to save the merged snapshot
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Format of the merged snapshot: `json` or `binary`
+    #[arg(long, default_value_t = SnapshotFormat::Json)]
+    format: SnapshotFormat,
+
+    /// Only warn if the cloud type or the TEE/TPM policies differ between the snapshots
+    /// (the ones of the first snapshot are kept)
+    #[arg(long)]
+    allow_conflicts: bool,
+}
+
+impl MergeArgs {
+    pub fn run(&self) -> Result<()> {
+        let merged = merge_snapshots(
+            self.snapshots
+                .iter()
+                .map(|path| Ok(SnapshotFile::from_bytes(&fs::read(path)?)?.into_snapshot()))
+                .collect::<Result<Vec<_>>>()?,
+        )?;
+
+        for conflict in &merged.conflicts {
+            if self.allow_conflicts {
+                println!("[ WARNING ] The {conflict} differs between the snapshots: the one of the first snapshot is kept");
+            } else {
+                anyhow::bail!("The {conflict} differs between the snapshots (use --allow-conflicts to keep the one of the first snapshot)");
+            }
+        }
+
+        fs::write(
+            &self.output,
+            SnapshotFile::Snapshot(Box::new(merged.snapshot)).to_bytes(self.format)?,
+        )?;
+
+        println!(
+            "The {} snapshots have been merged at: {}",
+            self.snapshots.len(),
+            self.output.to_string_lossy()
+        );
+
+        println!("[ WARNING ] The merged snapshot is unsigned: the envelopes proving the provenance of the snapshots are dropped and `snapshot verify-file` rejects it");
+
+        Ok(())
+    }
+}
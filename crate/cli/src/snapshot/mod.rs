@@ -0,0 +1,135 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use anyhow::{bail, Result};
+use clap::{Args, Subcommand};
+use cosmian_vm_client::{
+    client::CosmianVmClient,
+    envelope::{SnapshotEnvelope, SnapshotFile, SnapshotFormat},
+    pcr::PcrSelection,
+    snapshot::SnapshotPhase,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use convert::ConvertArgs;
+use diff::DiffArgs;
+use merge::MergeArgs;
+use verify_file::VerifyFileArgs;
+
+pub mod convert;
+pub mod diff;
+pub mod merge;
+pub mod verify_file;
+
+/// Snapshot a cosmian VM
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct SnapshotArgs {
+    #[command(subcommand)]
+    command: Option<SnapshotCommands>,
+
+    /// Path to save the snapshot
+    #[arg(short, long, default_value = PathBuf::from("./cosmian_vm.snapshot").into_os_string())]
+    output: PathBuf,
+
+    /// Format of the snapshot file: `json` or `binary` (zstd-compressed CBOR, much smaller)
+    #[arg(long, default_value_t = SnapshotFormat::Json)]
+    format: SnapshotFormat,
+
+    /// Hash all the files again instead of reusing the hashes of the previous snapshot
+    #[arg(long)]
+    full: bool,
+
+    /// PCRs whose values are recorded in the snapshot and compared by `verify`
+    /// (e.g. `0-7` for the firmware, the bootloader and Secure Boot)
+    #[arg(long)]
+    pcrs: Option<PcrSelection>,
+
+    /// Cancel the snapshot being processed by the agent instead of creating one
+    #[arg(long, conflicts_with = "full")]
+    cancel: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCommands {
+    VerifyFile(VerifyFileArgs),
+    Convert(ConvertArgs),
+    Diff(DiffArgs),
+    Merge(MergeArgs),
+}
+
+impl SnapshotArgs {
+    pub async fn run(&self, client: Option<&CosmianVmClient>) -> Result<()> {
+        match &self.command {
+            Some(SnapshotCommands::VerifyFile(args)) => return args.run().await,
+            Some(SnapshotCommands::Convert(args)) => return args.run(),
+            Some(SnapshotCommands::Diff(args)) => return args.run(),
+            Some(SnapshotCommands::Merge(args)) => return args.run(),
+            None => (),
+        }
+
+        let client = client
+            .ok_or_else(|| anyhow::anyhow!("The argument --url is required to snapshot a VM"))?;
+
+        if self.cancel {
+            client.cancel_snapshot().await?;
+            println!("The snapshot has been cancelled");
+            return Ok(());
+        }
+
+        println!("Processing the snapshot...");
+
+        // Reset the previous snapshot (or fail if the snapshot process is still running)
+        client.reset_snapshot().await?;
+
+        let envelope =
+            wait_snapshot(client, self.full, &self.pcrs.clone().unwrap_or_default()).await?;
+        let skipped_files = envelope.snapshot.skipped_files;
+        fs::write(
+            &self.output,
+            SnapshotFile::Envelope(Box::new(envelope)).to_bytes(self.format)?,
+        )?;
+
+        println!(
+            "The snapshot has been saved at: {}",
+            self.output.to_string_lossy()
+        );
+
+        if let Some(skipped_files @ 1..) = skipped_files {
+            println!("[ WARNING ] {skipped_files} files can't be read by the agent and are missing from the snapshot");
+        }
+
+        Ok(())
+    }
+}
+
+/// Order the snapshot and display its progress until it's ready
+async fn wait_snapshot(
+    client: &CosmianVmClient,
+    full: bool,
+    pcrs: &PcrSelection,
+) -> Result<SnapshotEnvelope> {
+    let progress_bar = ProgressBar::new(0).with_style(ProgressStyle::with_template(
+        "{spinner} [{elapsed_precise}] {msg} {wide_bar} {pos}/{len} files",
+    )?);
+    progress_bar.enable_steady_tick(Duration::from_millis(200));
+
+    loop {
+        // A failed snapshot is never returned: stop polling it
+        let status = client.snapshot_status().await?;
+        if let SnapshotPhase::Failed(error) = &status.phase {
+            progress_bar.abandon();
+            bail!("The snapshot has failed on the agent: {error}");
+        }
+
+        if let Some(envelope) = client.poll_snapshot_envelope(full, pcrs).await? {
+            progress_bar.finish_and_clear();
+            return Ok(envelope);
+        }
+
+        progress_bar.set_message(status.phase.to_string());
+        progress_bar.set_length(status.files_total);
+        progress_bar.set_position(status.files_done);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
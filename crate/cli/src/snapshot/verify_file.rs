@@ -0,0 +1,116 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::Args;
+use cosmian_vm_client::{
+    cloud_provider::CloudProvider,
+    envelope::{SnapshotFile, SNAPSHOT_ENVELOPE_VERSION},
+};
+use tee_attestation::{
+    az_verify_quote as az_tee_verify_quote, forge_report_data_with_nonce,
+    verify_quote as tee_verify_quote,
+};
+use tokio::task::spawn_blocking;
+use x509_cert::{der::Encode, Certificate};
+
+/// Verify the provenance of a snapshot file (without connecting to the Cosmian VM)
+#[derive(Args, Debug)]
+pub struct VerifyFileArgs {
+    /// Path of the Cosmian VM snapshot
+    snapshot: PathBuf,
+
+    /// Path of the expected agent TLS certificate (PEM or DER)
+    #[arg(long)]
+    certificate: PathBuf,
+}
+
+impl VerifyFileArgs {
+    pub async fn run(&self) -> Result<()> {
+        println!("Reading the snapshot...");
+
+        let SnapshotFile::Envelope(envelope) =
+            SnapshotFile::from_bytes(&fs::read(&self.snapshot)?)?
+        else {
+            anyhow::bail!(
+                "The snapshot is not wrapped into an envelope: its provenance can't be verified"
+            );
+        };
+
+        if envelope.version > SNAPSHOT_ENVELOPE_VERSION {
+            anyhow::bail!(
+                "Unsupported envelope version {} (expecting at most {SNAPSHOT_ENVELOPE_VERSION}): please update the cosmian_vm cli",
+                envelope.version
+            );
+        }
+
+        println!("Hostname: {}", envelope.hostname);
+        println!("Created at: {} (UNIX time)", envelope.created_at);
+        println!("Agent version: {}", envelope.agent_version);
+        println!(
+            "Certificate fingerprint: {}",
+            envelope.certificate_fingerprint
+        );
+
+        if envelope.certificate != read_certificate(&self.certificate)? {
+            println!("[ FAIL ] Verifying the agent certificate");
+            anyhow::bail!(
+                "The snapshot has not been produced by the agent of the given certificate"
+            );
+        }
+
+        println!("[ OK ] Verifying the agent certificate");
+
+        if let Err(e) = envelope.verify_signature() {
+            println!("[ FAIL ] Verifying the signature of the snapshot");
+            return Err(e.into());
+        }
+
+        println!("[ OK ] Verifying the signature of the snapshot");
+
+        let mut policy = envelope.snapshot.tee_policy.clone();
+        let quote = envelope.tee_quote.clone();
+
+        match envelope.snapshot.cloud_type {
+            Some(CloudProvider::GCP | CloudProvider::AWS) | None => {
+                policy.set_report_data(&forge_report_data_with_nonce(
+                    &envelope.report_data_nonce()?,
+                    &envelope.certificate,
+                )?)?;
+                spawn_blocking(move || tee_verify_quote(&quote, Some(&policy))).await??;
+            }
+            Some(CloudProvider::Azure) => {
+                spawn_blocking(move || az_tee_verify_quote(&quote, &policy)).await??;
+            }
+        }
+
+        if !envelope.is_body_bound() {
+            println!("[ FAIL ] Verifying TEE attestation of the snapshot");
+            anyhow::bail!(
+                "The TEE quote can't bind the snapshot content on {:?}: its provenance relies on the agent certificate only",
+                envelope.snapshot.cloud_type
+            );
+        }
+
+        println!("[ OK ] Verifying TEE attestation of the snapshot");
+
+        Ok(())
+    }
+}
+
+/// Read the certificate (PEM or DER) at `path` as DER
+fn read_certificate(path: &Path) -> Result<Vec<u8>> {
+    let content = fs::read(path)?;
+    if !content.starts_with(b"-----BEGIN") {
+        return Ok(content);
+    }
+
+    Certificate::load_pem_chain(&content)
+        .map_err(|e| anyhow::anyhow!("Invalid agent certificate {path:?}: {e}"))?
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No certificate in {path:?}"))?
+        .to_der()
+        .map_err(|e| anyhow::anyhow!("Invalid agent certificate {path:?}: {e}"))
+}
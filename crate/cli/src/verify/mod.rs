@@ -0,0 +1,877 @@
+use anyhow::Result;
+use clap::Args;
+use cosmian_vm_client::{
+    client::{
+        get_server_certificate_from_url, tpm_key_binding_nonce, CosmianVmClient, ImaBinaryResponse,
+        TpmQuoteResponse,
+    },
+    cloud_provider::CloudProvider,
+    credential::{attestation_key_name, make_credential, verify_ek_certificate, EkPublicKey},
+    envelope::SnapshotFile,
+    merge::merge_snapshots,
+    pcr::{quoted_pcr_selection, quoted_pcr_values, PcrBank, PcrSelection, PcrValues},
+    snapshot::{CosmianVmSnapshot, SnapshotFiles},
+};
+use ima::{ima::Ima, whitelist::Whitelist};
+use rand::RngCore;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tee_attestation::{
+    az_verify_quote as az_tee_verify_quote, forge_report_data_with_nonce,
+    verify_quote as tee_verify_quote,
+};
+use tokio::task::spawn_blocking;
+use tpm_quote::{verify_quote as tpm_verify_quote, PcrHashMethod};
+use x509_cert::{der::Decode, Certificate};
+
+use report::{CheckKind, ReportFormat, UnknownImaEntry, VerifyReport};
+
+pub mod report;
+
+const IMA_INTEGRITY: &str = "Verifying VM integrity";
+const TPM_ATTESTATION: &str = "Verifying TPM attestation";
+const TEE_ATTESTATION: &str = "Verifying TEE attestation";
+const BOOT_CHAIN: &str = "Verifying boot chain";
+const TPM_ENDORSEMENT: &str = "Verifying TPM endorsement";
+
+/// Verify a Cosmian VM
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Path of the Cosmian VM snapshot
+    ///
+    /// Repeat it to verify against the union of several snapshots of VMs created from the same image
+    #[arg(short, long, required = true)]
+    snapshot: Vec<PathBuf>,
+
+    /// Path of the whitelist (TOML) of the files allowed even if they are not in the snapshot
+    #[arg(short, long)]
+    whitelist: Option<PathBuf>,
+
+    /// Application urls (`domain_name:port`) to verify against Cosmian VM TLS certificate
+    #[arg(short, long)]
+    application: Option<Vec<String>>,
+
+    /// Format of the verification report
+    ///
+    /// The process exits with a code specific to the class of the first failed check:
+    /// 2 (IMA integrity), 3 (TPM attestation), 4 (TEE attestation), 5 (application TLS),
+    /// 6 (boot chain), 7 (TPM endorsement)
+    #[arg(long, value_enum, default_value_t)]
+    output: ReportFormat,
+
+    /// Run all the checks even if one fails, and summarise them at the end
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Path of the TPM manufacturer CA certificates (PEM) the EK certificate must be issued by
+    ///
+    /// The TPM attestation key is then proven to live in the TPM of the certified EK
+    #[arg(long)]
+    tpm_ek_ca: Option<PathBuf>,
+}
+
+/// The IMA entries and the TPM quote fetched from the Cosmian VM
+struct ImaCollaterals {
+    /// The number of entries preceding `ima` in the IMA log
+    first_entry: usize,
+    /// The entries not verified yet
+    ima: Ima,
+    /// The PCR extended by IMA
+    ima_pcr: u8,
+    /// The PCR value after the replay of the whole IMA log
+    ima_pcr_value: Vec<u8>,
+    /// The quote of the PCR extended by IMA and of the PCRs of the boot chain
+    tpm_quote: TpmQuoteResponse,
+    /// The TEE quote taken with the TPM quote, binding its attestation key
+    tee_quote: Vec<u8>,
+}
+
+impl VerifyArgs {
+    pub async fn run(&self, client: &CosmianVmClient) -> Result<()> {
+        let mut report = VerifyReport::new(&[0; 32], self.output);
+
+        report.info("Reading the snapshot...");
+        let reference = Reference::load(&self.snapshot, self.whitelist.as_deref())?;
+        let tpm_ek_ca = self.tpm_ek_ca.as_deref().map(load_tpm_ek_ca).transpose()?;
+
+        Verification {
+            reference: &reference,
+            applications: self.application.as_deref().unwrap_or_default(),
+            keep_going: self.keep_going,
+            tpm_ek_ca: tpm_ek_ca.as_deref(),
+        }
+        .run(client, &mut report, &mut ImaState::default())
+        .await;
+
+        report.finish()
+    }
+}
+
+/// Read the TPM manufacturer CA certificates (PEM) at `path`
+pub fn load_tpm_ek_ca(path: &Path) -> Result<Vec<Certificate>> {
+    let certificates = Certificate::load_pem_chain(&fs::read(path)?)
+        .map_err(|e| anyhow::anyhow!("Invalid TPM manufacturer CA certificates {path:?}: {e}"))?;
+    if certificates.is_empty() {
+        anyhow::bail!("No TPM manufacturer CA certificate in {path:?}");
+    }
+    Ok(certificates)
+}
+
+/// The snapshot (and the optional whitelist) a Cosmian VM is verified against
+pub struct Reference {
+    pub snapshot: CosmianVmSnapshot,
+    pub whitelist: Option<Whitelist>,
+}
+
+impl Reference {
+    /// Read the union of the `snapshots` and the `whitelist`
+    pub fn load(snapshots: &[PathBuf], whitelist: Option<&Path>) -> Result<Self> {
+        let merged = merge_snapshots(
+            snapshots
+                .iter()
+                .map(|path| Ok(SnapshotFile::from_bytes(&fs::read(path)?)?.into_snapshot()))
+                .collect::<Result<Vec<_>>>()?,
+        )?;
+        if let Some(conflict) = merged.conflicts.first() {
+            anyhow::bail!(
+                "The {conflict} differs between the snapshots: they can't be verified together"
+            );
+        }
+
+        let whitelist = whitelist
+            .map(|whitelist| -> Result<_> {
+                Whitelist::try_from(fs::read_to_string(whitelist)?.as_str())
+                    .map_err(|e| anyhow::anyhow!("Invalid whitelist {whitelist:?}: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            snapshot: merged.snapshot,
+            whitelist,
+        })
+    }
+}
+
+/// The IMA entries of a Cosmian VM already verified
+///
+/// The IMA log only grows until the next reboot and the whole log is attested by the TPM
+/// quote, so only the entries added since the previous verification need to be fetched,
+/// replayed from the PCR value reached so far and compared
+#[derive(Clone, Debug, Default)]
+pub struct ImaState {
+    verified_entries: usize,
+    /// The PCR value after the replay of the verified entries
+    pcr_value: Option<Vec<u8>>,
+    /// The PCR extended by IMA, given by the verified entries
+    pcr_id: Option<u8>,
+}
+
+impl ImaState {
+    /// Skip the IMA entries of `collaterals` at the next verification only if they are in
+    /// the reference (`ima_verified`) and have been attested by the TPM (`tpm_attested`)
+    fn advance(&mut self, collaterals: &ImaCollaterals, ima_verified: bool, tpm_attested: bool) {
+        if !ima_verified || !tpm_attested {
+            return;
+        }
+
+        self.verified_entries = collaterals.first_entry + collaterals.ima.entries.len();
+        self.pcr_value = Some(collaterals.ima_pcr_value.clone());
+        self.pcr_id = Some(collaterals.ima_pcr);
+    }
+
+    /// The entry number to fetch the IMA log from: the verified entries are skipped
+    fn next_entry(&self) -> Option<usize> {
+        self.pcr_value.as_ref().map(|_| self.verified_entries)
+    }
+
+    /// Whether the IMA log has been reset since the verified entries (e.g. the VM rebooted),
+    /// given the entries `ima` returned from `next_entry()`
+    ///
+    /// The agent returns the whole log if it's shorter than the verified entries,
+    /// and a log replaced by a longer one doesn't extend the PCR value reached so far
+    fn is_reset_by(&self, ima: &ImaBinaryResponse) -> bool {
+        self.pcr_value.as_ref().is_some_and(|pcr_value| {
+            ima.from_entry != self.verified_entries || *pcr_value != ima.pcr_value
+        })
+    }
+
+    /// The PCR extended by the IMA entries `ima` following the verified entries
+    fn ima_pcr(&self, ima: &Ima) -> u8 {
+        match (ima.entries.is_empty(), self.pcr_id) {
+            (true, Some(pcr_id)) => pcr_id,
+            _ => ima.pcr_id() as u8,
+        }
+    }
+
+    /// The number of IMA entries verified so far
+    #[must_use]
+    pub const fn verified_entries(&self) -> usize {
+        self.verified_entries
+    }
+}
+
+/// The checks run to verify a Cosmian VM against a reference
+pub struct Verification<'a> {
+    pub reference: &'a Reference,
+    /// Application urls to verify against Cosmian VM TLS certificate
+    pub applications: &'a [String],
+    /// Run all the checks even if one fails
+    pub keep_going: bool,
+    /// The TPM manufacturer CA certificates the EK certificate must be issued by
+    pub tpm_ek_ca: Option<&'a [Certificate]>,
+}
+
+impl Verification<'_> {
+    /// Run the checks against the Cosmian VM reached by `client` and record them in `report`
+    ///
+    /// Only the IMA entries not in `ima_state` are compared to the reference. `ima_state` is
+    /// updated once the IMA log matches the reference and has been attested by the TPM
+    pub async fn run(
+        &self,
+        client: &CosmianVmClient,
+        report: &mut VerifyReport,
+        ima_state: &mut ImaState,
+    ) {
+        let snapshot = &self.reference.snapshot;
+
+        report.info(&format!(
+            "Fetching the collaterals... (cloud_type: {:?})",
+            snapshot.cloud_type
+        ));
+
+        let mut nonce: [u8; 32] = [0u8; 32];
+        if let Some(cloud_type) = snapshot.cloud_type {
+            if cloud_type != CloudProvider::Azure && cloud_type != CloudProvider::AWS {
+                // Random nonce for all cloud provider except Microsoft Azure
+                // because REPORT_DATA can't be set in quote
+                rand::thread_rng().fill_bytes(&mut nonce);
+            }
+        }
+        report.nonce = hex::encode(nonce);
+
+        // The TEE quote taken at once with the TPM quote and the IMA log,
+        // with the public key of the TPM attestation key it binds
+        let mut tee_quote = None;
+
+        match &snapshot.filehashes {
+            None => {
+                report.info("[ WARNING ] No files hash in the snapshot");
+                report.skip(
+                    CheckKind::ImaIntegrity,
+                    IMA_INTEGRITY,
+                    "no files hash in the snapshot",
+                );
+                report.skip(
+                    CheckKind::TpmAttestation,
+                    TPM_ATTESTATION,
+                    "no files hash in the snapshot",
+                );
+                report.skip(
+                    CheckKind::BootChain,
+                    BOOT_CHAIN,
+                    "no files hash in the snapshot",
+                );
+                report.skip(
+                    CheckKind::TpmEndorsement,
+                    TPM_ENDORSEMENT,
+                    "no files hash in the snapshot",
+                );
+            }
+            Some(filehashes) => {
+                let started = Instant::now();
+                let pcrs = snapshot
+                    .pcr_values
+                    .as_ref()
+                    .map(|pcr_values| pcr_values.keys().copied().collect())
+                    .unwrap_or_default();
+                let collaterals =
+                    fetch_ima_collaterals(client, &nonce, ima_state, &pcrs, snapshot.pcr_bank)
+                        .await;
+                if let Ok(collaterals) = &collaterals {
+                    tee_quote = Some((
+                        collaterals.tee_quote.clone(),
+                        collaterals.tpm_quote.public_key.clone(),
+                    ));
+                }
+
+                let result = match &collaterals {
+                    Ok(collaterals) => check_ima_integrity(
+                        report,
+                        collaterals,
+                        filehashes,
+                        self.reference.whitelist.as_ref(),
+                    ),
+                    Err(e) => Err(anyhow::anyhow!("Can't fetch the IMA log: {e:#}")),
+                };
+                let ima_verified =
+                    report.record(CheckKind::ImaIntegrity, IMA_INTEGRITY, started, result);
+
+                let tpm_attested = match &collaterals {
+                    _ if self.must_stop(report) => {
+                        report.skip(
+                            CheckKind::TpmAttestation,
+                            TPM_ATTESTATION,
+                            "a previous check failed",
+                        );
+                        false
+                    }
+                    Err(_) => {
+                        report.skip(
+                            CheckKind::TpmAttestation,
+                            TPM_ATTESTATION,
+                            "the TPM quote can't be fetched",
+                        );
+                        false
+                    }
+                    Ok(collaterals) => {
+                        let started = Instant::now();
+                        let result = check_tpm_attestation(collaterals, snapshot, &nonce);
+                        let tpm_attested = report.record(
+                            CheckKind::TpmAttestation,
+                            TPM_ATTESTATION,
+                            started,
+                            result,
+                        );
+                        ima_state.advance(collaterals, ima_verified, tpm_attested);
+                        tpm_attested
+                    }
+                };
+
+                match (&snapshot.pcr_values, &collaterals) {
+                    (None, _) => report.skip(
+                        CheckKind::BootChain,
+                        BOOT_CHAIN,
+                        "no PCR values in the snapshot",
+                    ),
+                    (Some(_), _) if self.must_stop(report) => {
+                        report.skip(CheckKind::BootChain, BOOT_CHAIN, "a previous check failed");
+                    }
+                    (Some(expected), Ok(collaterals)) if tpm_attested => {
+                        let started = Instant::now();
+                        let result = check_boot_chain(expected, &collaterals.tpm_quote.pcr_values);
+                        report.record(CheckKind::BootChain, BOOT_CHAIN, started, result);
+                    }
+                    (Some(_), _) => report.skip(
+                        CheckKind::BootChain,
+                        BOOT_CHAIN,
+                        "the TPM quote can't be verified",
+                    ),
+                }
+
+                match (self.tpm_ek_ca, &collaterals) {
+                    (None, _) => report.skip(
+                        CheckKind::TpmEndorsement,
+                        TPM_ENDORSEMENT,
+                        "no TPM manufacturer CA given",
+                    ),
+                    (Some(_), _) if self.must_stop(report) => report.skip(
+                        CheckKind::TpmEndorsement,
+                        TPM_ENDORSEMENT,
+                        "a previous check failed",
+                    ),
+                    (Some(tpm_ek_ca), Ok(collaterals)) if tpm_attested => {
+                        let started = Instant::now();
+                        let result = check_tpm_endorsement(
+                            client,
+                            &collaterals.tpm_quote.public_key,
+                            tpm_ek_ca,
+                        )
+                        .await;
+                        report.record(CheckKind::TpmEndorsement, TPM_ENDORSEMENT, started, result);
+                    }
+                    (Some(_), _) => report.skip(
+                        CheckKind::TpmEndorsement,
+                        TPM_ENDORSEMENT,
+                        "the TPM quote can't be verified",
+                    ),
+                }
+            }
+        };
+
+        if self.must_stop(report) {
+            report.skip(
+                CheckKind::TeeAttestation,
+                TEE_ATTESTATION,
+                "a previous check failed",
+            );
+        } else {
+            let started = Instant::now();
+            let result = check_tee_attestation(tee_quote, snapshot, &nonce, client).await;
+            report.record(CheckKind::TeeAttestation, TEE_ATTESTATION, started, result);
+        }
+
+        for application_url in self.applications {
+            let mut application_url = application_url.clone();
+            if !application_url.starts_with("http://") && !application_url.starts_with("https://") {
+                application_url.insert_str(0, "https://");
+            }
+
+            let name = format!("Verifying TLS application for {application_url}");
+            if self.must_stop(report) {
+                report.skip(CheckKind::ApplicationTls, &name, "a previous check failed");
+            } else {
+                let started = Instant::now();
+                let result = check_application_tls(&application_url, client).await;
+                report.record(CheckKind::ApplicationTls, &name, started, result);
+            }
+        }
+    }
+
+    /// Whether the remaining checks must be skipped because a check failed
+    fn must_stop(&self, report: &VerifyReport) -> bool {
+        !self.keep_going && report.has_failed()
+    }
+}
+
+/// Replay the IMA entries following the first `first_entry` entries of the log
+fn replay_ima(
+    ima: &Ima,
+    first_entry: usize,
+    ima_state: &ImaState,
+    pcr_hash_method: PcrHashMethod,
+) -> Result<Vec<u8>> {
+    Ok(match &ima_state.pcr_value {
+        Some(pcr_value) if first_entry > 0 => ima.pcr_value_from(pcr_hash_method, pcr_value)?,
+        _ => ima.pcr_value(pcr_hash_method)?,
+    })
+}
+
+/// Fetch the IMA entries not verified yet with the TPM and TEE quotes taken at once,
+/// for the PCR extended by IMA and the PCRs `pcrs` in the PCR bank `hash_bank`
+/// (or the default bank of the agent)
+///
+/// The IMA entries returned by the agent match the quoted PCR: no retry is needed.
+/// The TEE quote binds the TPM attestation key: there is no fallback on the separate
+/// endpoints of a former agent, which can't bind it
+///
+/// `ima_state` is reset if the IMA log has been reset since the previous verification
+/// or if the new entries can't be fetched: the whole log is then fetched and verified again
+async fn fetch_ima_collaterals(
+    client: &CosmianVmClient,
+    nonce: &[u8],
+    ima_state: &mut ImaState,
+    pcrs: &PcrSelection,
+    hash_bank: Option<PcrBank>,
+) -> Result<ImaCollaterals> {
+    let from_entry = ima_state.next_entry();
+    let response = match client.attest(nonce, from_entry, pcrs, hash_bank).await {
+        Ok(response) if !ima_state.is_reset_by(&response.ima) => Some(response),
+        Ok(response) => {
+            tracing::debug!("The IMA log has been reset since the previous verification");
+            *ima_state = ImaState::default();
+            // The whole log is already returned if it's shorter than the verified entries
+            (response.ima.from_entry == 0).then_some(response)
+        }
+        Err(e) if from_entry.is_some() => {
+            tracing::debug!("Can't fetch the new IMA entries, fetching the whole log: {e}");
+            *ima_state = ImaState::default();
+            None
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let response = match response {
+        Some(response) => response,
+        None => client.attest(nonce, None, pcrs, hash_bank).await?,
+    };
+
+    let first_entry = response.ima.from_entry;
+    if first_entry != 0 && Some(first_entry) != ima_state.next_entry() {
+        anyhow::bail!("Unexpected IMA entries from the entry {first_entry}");
+    }
+
+    let ima = Ima::try_from(&response.ima.entries[..])?;
+    let ima_pcr_value = replay_ima(
+        &ima,
+        first_entry,
+        ima_state,
+        response.tpm_quote.pcr_value_hash_method.clone(),
+    )?;
+
+    if first_entry == 0 && ima.entries.is_empty() {
+        anyhow::bail!("No IMA list recovered");
+    }
+
+    Ok(ImaCollaterals {
+        first_entry,
+        ima_pcr: ima_state.ima_pcr(&ima),
+        ima,
+        ima_pcr_value,
+        tpm_quote: response.tpm_quote,
+        tee_quote: response.tee_quote,
+    })
+}
+
+/// Check that the IMA entries not verified yet are in the snapshot or in the whitelist
+///
+/// The unknown entries are added to the `report`
+fn check_ima_integrity(
+    report: &mut VerifyReport,
+    collaterals: &ImaCollaterals,
+    filehashes: &SnapshotFiles,
+    whitelist: Option<&Whitelist>,
+) -> Result<String> {
+    let failures = collaterals.ima.compare(&filehashes.0, whitelist);
+
+    report
+        .unknown_ima_entries
+        .extend(failures.entries.iter().map(|entry| UnknownImaEntry {
+            path: entry.filename_hint.clone(),
+            hash_method: format!("{:?}", entry.filedata_hash_method).to_lowercase(),
+            hash: hex::encode(&entry.filedata_hash),
+        }));
+
+    if !failures.entries.is_empty() {
+        failures.entries.iter().for_each(|entry| {
+            report.info(&format!(
+                "Entry ({},{}) can't be found in the snapshot!",
+                entry.filename_hint,
+                hex::encode(&entry.filedata_hash)
+            ));
+        });
+        anyhow::bail!(
+            "Unexpected binaries found! ({} unknown entries)",
+            failures.entries.len()
+        );
+    }
+
+    if collaterals.first_entry > 0 {
+        return Ok(format!(
+            "{} new IMA entries against {} files",
+            collaterals.ima.entries.len(),
+            filehashes.0.len()
+        ));
+    }
+
+    Ok(format!("against {} files", filehashes.0.len()))
+}
+
+/// Check the TPM quote of the PCR extended by IMA, whose value is given by the replay
+/// of the IMA log, and of the PCRs of the boot chain, whose values are given by the agent
+///
+/// The quote must attest exactly these PCRs (the ones of the snapshot if any, the ones
+/// configured in the agent otherwise), in the PCR bank of the snapshot
+fn check_tpm_attestation(
+    collaterals: &ImaCollaterals,
+    snapshot: &CosmianVmSnapshot,
+    nonce: &[u8],
+) -> Result<String> {
+    let mut expected_pcrs: PcrSelection = snapshot
+        .pcr_values
+        .as_ref()
+        .unwrap_or(&collaterals.tpm_quote.pcr_values)
+        .keys()
+        .copied()
+        .collect();
+    expected_pcrs.insert(collaterals.ima_pcr);
+    let expected_bank = snapshot
+        .pcr_bank
+        .unwrap_or_else(|| PcrBank::from(&collaterals.tpm_quote.pcr_value_hash_method));
+
+    let (bank, pcrs) = quoted_pcr_selection(&collaterals.tpm_quote.quote)?;
+    if bank != expected_bank || pcrs != expected_pcrs {
+        anyhow::bail!(
+            "The TPM quote attests the PCRs {pcrs} of the {bank} bank instead of the PCRs {expected_pcrs} of the {expected_bank} bank"
+        );
+    }
+
+    tpm_verify_quote(
+        &collaterals.tpm_quote.quote,
+        &collaterals.tpm_quote.signature,
+        &collaterals.tpm_quote.public_key,
+        Some(nonce),
+        &quoted_pcr_values(
+            &collaterals.tpm_quote.pcr_values,
+            collaterals.ima_pcr,
+            &collaterals.ima_pcr_value,
+        )?,
+        snapshot
+            .tpm_policy
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("TPM policy is missing in the snapshot"))?,
+    )?;
+
+    Ok(String::new())
+}
+
+/// Check that the values of the PCRs of the boot chain attested by the TPM quote
+/// are the ones recorded in the snapshot
+fn check_boot_chain(expected: &PcrValues, quoted: &PcrValues) -> Result<String> {
+    let mismatches = expected
+        .iter()
+        .filter_map(|(pcr, value)| match quoted.get(pcr) {
+            None => Some(format!("PCR {pcr} is not quoted")),
+            Some(quoted) if !quoted.eq_ignore_ascii_case(value) => {
+                Some(format!("PCR {pcr} is {quoted} instead of {value}"))
+            }
+            Some(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    if !mismatches.is_empty() {
+        anyhow::bail!(
+            "The boot chain differs from the snapshot: {}",
+            mismatches.join(", ")
+        );
+    }
+
+    Ok(format!(
+        "PCRs {}",
+        expected.keys().copied().collect::<PcrSelection>()
+    ))
+}
+
+/// Check that the attestation key signing the TPM quotes lives in the same TPM as an
+/// endorsement key certified by one of the TPM manufacturer CAs `tpm_ek_ca`
+///
+/// The agent must recover a random credential encrypted for the certified EK and bound to
+/// the name of the attestation key, which only the TPM holding both keys can do
+async fn check_tpm_endorsement(
+    client: &CosmianVmClient,
+    tpm_public_key: &[u8],
+    tpm_ek_ca: &[Certificate],
+) -> Result<String> {
+    let ek_certificate = client
+        .tpm_ek_certificate()
+        .await
+        .map_err(|e| anyhow::anyhow!("Can't fetch the EK certificate: {e}"))?;
+    let ek_certificate = Certificate::from_der(&ek_certificate)
+        .map_err(|e| anyhow::anyhow!("Invalid EK certificate: {e}"))?;
+    verify_ek_certificate(&ek_certificate, tpm_ek_ca)?;
+
+    let mut credential = [0; 32];
+    rand::thread_rng().fill_bytes(&mut credential);
+    let (credential_blob, encrypted_secret) = make_credential(
+        &EkPublicKey::from_certificate(&ek_certificate)?,
+        &attestation_key_name(tpm_public_key)?,
+        &credential,
+    )?;
+
+    let activated = client
+        .tpm_activate_credential(&credential_blob, &encrypted_secret)
+        .await
+        .map_err(|e| anyhow::anyhow!("Can't activate the credential: {e}"))?;
+    if activated != credential {
+        anyhow::bail!("The TPM attestation key doesn't live in the TPM of the EK certificate");
+    }
+
+    Ok(format!(
+        "EK certified by {}",
+        ek_certificate.tbs_certificate.issuer
+    ))
+}
+
+/// Verify the TEE quote, fetched from the Cosmian VM if not already given
+///
+/// A TEE quote given with the public key of the TPM attestation key must bind it in its
+/// report data: it checks that the TPM quote comes from the TPM of the confidential VM
+async fn check_tee_attestation(
+    quote: Option<(Vec<u8>, Vec<u8>)>,
+    snapshot: &CosmianVmSnapshot,
+    nonce: &[u8; 32],
+    client: &CosmianVmClient,
+) -> Result<String> {
+    let (quote, report_data_nonce, details) = match quote {
+        Some((quote, tpm_public_key)) => (
+            quote,
+            tpm_key_binding_nonce(nonce, &tpm_public_key),
+            "the TPM attestation key is bound",
+        ),
+        None => (
+            client
+                .tee_quote(nonce)
+                .await
+                .map_err(|e| anyhow::anyhow!("Can't fetch the TEE quote: {e}"))?,
+            *nonce,
+            "",
+        ),
+    };
+
+    if snapshot.cloud_type == Some(CloudProvider::Azure) {
+        // The report data can't be set on Azure: nothing is bound
+        let policy = snapshot.tee_policy.clone();
+        spawn_blocking(move || az_tee_verify_quote(&quote, &policy)).await??;
+        return Ok(String::new());
+    }
+
+    verify_bound_tee_quote(quote, snapshot, &report_data_nonce, &client.certificate.0)
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "{e} (the report data must bind the nonce, the TLS certificate{})",
+                if details.is_empty() {
+                    ""
+                } else {
+                    " and the TPM attestation key"
+                }
+            )
+        })?;
+
+    Ok(details.to_owned())
+}
+
+/// Verify the TEE quote `quote` against the TEE policy of the snapshot, with a report data
+/// binding `report_data_nonce` and the TLS certificate `certificate` of the Cosmian VM Agent
+pub async fn verify_bound_tee_quote(
+    quote: Vec<u8>,
+    snapshot: &CosmianVmSnapshot,
+    report_data_nonce: &[u8; 32],
+    certificate: &[u8],
+) -> Result<()> {
+    if snapshot.cloud_type == Some(CloudProvider::Azure) {
+        anyhow::bail!("The report data of the TEE quote can't be set on Azure");
+    }
+
+    let mut policy = snapshot.tee_policy.clone();
+    policy.set_report_data(&forge_report_data_with_nonce(
+        report_data_nonce,
+        certificate,
+    )?)?;
+    spawn_blocking(move || tee_verify_quote(&quote, Some(&policy))).await??;
+
+    Ok(())
+}
+
+/// Check that the application serves the TLS certificate of the Cosmian VM Agent
+async fn check_application_tls(application_url: &str, client: &CosmianVmClient) -> Result<String> {
+    // Getting the application certificate is blocking
+    let url = application_url.to_owned();
+    let app_certificate = spawn_blocking(move || get_server_certificate_from_url(&url))
+        .await?
+        .map_err(|e| {
+            anyhow::anyhow!("Can't get the application certificate for {application_url}: {e}")
+        })?;
+
+    if app_certificate != client.certificate.0 {
+        anyhow::bail!(
+            "TLS certificate for application {application_url} differs from Cosmian VM Agent TLS certificate"
+        );
+    }
+
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Instant};
+
+    use cosmian_vm_client::{
+        client::{ImaBinaryResponse, TpmQuoteResponse},
+        pcr::PcrValues,
+        snapshot::SnapshotFiles,
+    };
+    use ima::ima::Ima;
+    use tpm_quote::PcrHashMethod;
+
+    use super::{
+        check_ima_integrity,
+        report::{CheckKind, ReportFormat, VerifyReport},
+        ImaCollaterals, ImaState, IMA_INTEGRITY,
+    };
+
+    const IMA_LOG: [&str; 2] = [
+        "10 2c7020ad8cab6b7419e4973171cb704bdbf52f77 ima e09e048c48301268ff38645f4c006137e42951d0 /usr/bin/app",
+        "10 a84ff12e903a050abff2f336292d8318e7430a89 ima f4107171a62db56e4949c30fca97d09f7550aac5 /usr/bin/unknown",
+    ];
+
+    /// The IMA entries of `IMA_LOG` the agent returns given the entries verified so far
+    fn collaterals(ima_state: &ImaState) -> ImaCollaterals {
+        let first_entry = ima_state.verified_entries();
+        ImaCollaterals {
+            first_entry,
+            ima: Ima::try_from(IMA_LOG[first_entry..].join("\n").as_str()).unwrap(),
+            ima_pcr: 10,
+            ima_pcr_value: vec![1; 20],
+            tpm_quote: TpmQuoteResponse {
+                pcr_value_hash_method: PcrHashMethod::Sha1,
+                quote: vec![],
+                signature: vec![],
+                public_key: vec![],
+                pcr_values: PcrValues::new(),
+            },
+            tee_quote: vec![],
+        }
+    }
+
+    /// Verify the IMA entries against `files` as if the TPM attested them
+    fn verify(ima_state: &mut ImaState, files: &[(&str, &str)]) -> bool {
+        let filehashes = SnapshotFiles(
+            files
+                .iter()
+                .map(|(path, hash)| ((*path).to_owned(), hex::decode(hash).unwrap()))
+                .collect::<HashSet<_>>(),
+        );
+
+        let mut report = VerifyReport::new(&[0; 32], ReportFormat::Json);
+        let collaterals = collaterals(ima_state);
+        let result = check_ima_integrity(&mut report, &collaterals, &filehashes, None);
+        let ima_verified = report.record(
+            CheckKind::ImaIntegrity,
+            IMA_INTEGRITY,
+            Instant::now(),
+            result,
+        );
+        ima_state.advance(&collaterals, ima_verified, true);
+        ima_verified
+    }
+
+    #[test]
+    fn test_ima_state_after_failure() {
+        let app = ("/usr/bin/app", "e09e048c48301268ff38645f4c006137e42951d0");
+        let unknown = (
+            "/usr/bin/unknown",
+            "f4107171a62db56e4949c30fca97d09f7550aac5",
+        );
+
+        // The unknown entry makes the first round fail even though the TPM attests the log
+        let mut ima_state = ImaState::default();
+        assert!(!verify(&mut ima_state, &[app]));
+        assert_eq!(ima_state.verified_entries(), 0);
+
+        // The second round compares the unknown entry again
+        assert!(!verify(&mut ima_state, &[app]));
+        assert_eq!(ima_state.verified_entries(), 0);
+
+        assert!(verify(&mut ima_state, &[app, unknown]));
+        assert_eq!(ima_state.verified_entries(), IMA_LOG.len());
+
+        // Once verified, the entries are not compared anymore
+        assert!(verify(&mut ima_state, &[]));
+    }
+
+    #[test]
+    fn test_ima_state_after_reset() {
+        let app = ("/usr/bin/app", "e09e048c48301268ff38645f4c006137e42951d0");
+        let unknown = (
+            "/usr/bin/unknown",
+            "f4107171a62db56e4949c30fca97d09f7550aac5",
+        );
+        let mut ima_state = ImaState::default();
+        assert_eq!(ima_state.next_entry(), None);
+        assert!(verify(&mut ima_state, &[app, unknown]));
+        assert_eq!(ima_state.next_entry(), Some(IMA_LOG.len()));
+
+        let response = |from_entry, pcr_value| ImaBinaryResponse {
+            from_entry,
+            pcr_value,
+            pcr_value_hash_method: PcrHashMethod::Sha1,
+            entries: vec![],
+        };
+
+        // The log grew since the previous verification
+        assert!(!ima_state.is_reset_by(&response(IMA_LOG.len(), vec![1; 20])));
+
+        // The VM rebooted: the agent returns the whole log, shorter than the verified entries
+        assert!(ima_state.is_reset_by(&response(0, vec![0; 20])));
+
+        // The VM rebooted and its log is already longer than the verified entries
+        assert!(ima_state.is_reset_by(&response(IMA_LOG.len(), vec![2; 20])));
+
+        // Once reset, the whole log is verified again
+        ima_state = ImaState::default();
+        assert!(!verify(&mut ima_state, &[app]));
+        assert_eq!(ima_state.next_entry(), None);
+    }
+}
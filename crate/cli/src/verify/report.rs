@@ -0,0 +1,317 @@
+use std::{fmt, time::Instant};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// The output format of the verification report
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    /// `[ OK ]`/`[ FAIL ]` lines printed as the checks run
+    #[default]
+    Text,
+    Json,
+    /// JUnit XML (for CI gates)
+    Junit,
+}
+
+/// The classes of checks run to verify a Cosmian VM
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    ImaIntegrity,
+    TpmAttestation,
+    TeeAttestation,
+    ApplicationTls,
+    BootChain,
+    TpmEndorsement,
+}
+
+impl CheckKind {
+    /// The exit code of the process when a check of that class fails
+    #[must_use]
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Self::ImaIntegrity => 2,
+            Self::TpmAttestation => 3,
+            Self::TeeAttestation => 4,
+            Self::ApplicationTls => 5,
+            Self::BootChain => 6,
+            Self::TpmEndorsement => 7,
+        }
+    }
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::ImaIntegrity => "ima_integrity",
+            Self::TpmAttestation => "tpm_attestation",
+            Self::TeeAttestation => "tee_attestation",
+            Self::ApplicationTls => "application_tls",
+            Self::BootChain => "boot_chain",
+            Self::TpmEndorsement => "tpm_endorsement",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CheckStatus {
+    Ok,
+    Fail,
+    Skip,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckReport {
+    pub kind: CheckKind,
+    pub name: String,
+    pub status: CheckStatus,
+    pub details: String,
+    pub duration_ms: u64,
+}
+
+/// An IMA entry found neither in the snapshot nor in the whitelist
+#[derive(Clone, Debug, Serialize)]
+pub struct UnknownImaEntry {
+    pub path: String,
+    pub hash_method: String,
+    pub hash: String,
+}
+
+/// The result of all the checks run to verify a Cosmian VM
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    /// The nonce used in the TPM and TEE quotes (hex encoded)
+    pub nonce: String,
+    pub checks: Vec<CheckReport>,
+    pub unknown_ima_entries: Vec<UnknownImaEntry>,
+    pub duration_ms: u64,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    format: ReportFormat,
+}
+
+/// The error returned when a check fails, giving the exit code of the process
+#[derive(Debug)]
+pub struct VerifyError {
+    pub kind: CheckKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Verification failed ({})", self.kind)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl VerifyReport {
+    #[must_use]
+    pub fn new(nonce: &[u8], format: ReportFormat) -> Self {
+        Self {
+            nonce: hex::encode(nonce),
+            checks: vec![],
+            unknown_ima_entries: vec![],
+            duration_ms: 0,
+            started: Instant::now(),
+            format,
+        }
+    }
+
+    /// Print a progress message (text format only)
+    pub fn info(&self, message: &str) {
+        if self.format == ReportFormat::Text {
+            println!("{message}");
+        }
+    }
+
+    /// Record the result of a check started at `started`
+    ///
+    /// Return whether the check succeeded
+    pub fn record(
+        &mut self,
+        kind: CheckKind,
+        name: &str,
+        started: Instant,
+        result: Result<String, anyhow::Error>,
+    ) -> bool {
+        let (status, details) = match result {
+            Ok(details) => (CheckStatus::Ok, details),
+            Err(e) => (CheckStatus::Fail, format!("{e:#}")),
+        };
+        self.push(CheckReport {
+            kind,
+            name: name.to_owned(),
+            status,
+            details,
+            duration_ms: duration_ms(started),
+        });
+        status == CheckStatus::Ok
+    }
+
+    /// Record a check which has not been run
+    pub fn skip(&mut self, kind: CheckKind, name: &str, reason: &str) {
+        self.push(CheckReport {
+            kind,
+            name: name.to_owned(),
+            status: CheckStatus::Skip,
+            details: reason.to_owned(),
+            duration_ms: 0,
+        });
+    }
+
+    fn push(&mut self, check: CheckReport) {
+        if self.format == ReportFormat::Text {
+            match check.status {
+                CheckStatus::Ok if check.details.is_empty() => println!("[ OK ] {}", check.name),
+                CheckStatus::Ok => println!("[ OK ] {} ({})", check.name, check.details),
+                CheckStatus::Fail => println!("[ FAIL ] {}: {}", check.name, check.details),
+                CheckStatus::Skip => println!("[ SKIP ] {} ({})", check.name, check.details),
+            }
+        }
+        self.checks.push(check);
+    }
+
+    /// Whether a check failed so far
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.first_failure().is_some()
+    }
+
+    #[must_use]
+    pub fn first_failure(&self) -> Option<CheckKind> {
+        self.failures().next().map(|check| check.kind)
+    }
+
+    /// Print the report in its format and return the error matching the first failed check
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.close();
+
+        match self.format {
+            ReportFormat::Text => self.print_summary(),
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&self)?),
+            ReportFormat::Junit => println!("{}", self.to_junit()),
+        }
+
+        self.result()
+    }
+
+    /// Stop the timer of the report
+    pub fn close(&mut self) {
+        self.duration_ms = duration_ms(self.started);
+    }
+
+    /// The error matching the first failed check
+    pub fn result(&self) -> anyhow::Result<()> {
+        match self.first_failure() {
+            Some(kind) => Err(VerifyError { kind }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The failed checks
+    pub fn failures(&self) -> impl Iterator<Item = &CheckReport> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+    }
+
+    fn count(&self, status: CheckStatus) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+
+    /// Print the number of checks per status and the failed checks
+    fn print_summary(&self) {
+        println!(
+            "\nSummary: {} OK, {} FAIL, {} SKIP ({} ms)",
+            self.count(CheckStatus::Ok),
+            self.count(CheckStatus::Fail),
+            self.count(CheckStatus::Skip),
+            self.duration_ms
+        );
+        for check in self.failures() {
+            println!("  - [{}] {}: {}", check.kind, check.name, check.details);
+        }
+    }
+
+    /// Serialize the report as JUnit XML
+    #[must_use]
+    pub fn to_junit(&self) -> String {
+        let (tests, failures, skipped) = (
+            self.checks.len(),
+            self.count(CheckStatus::Fail),
+            self.count(CheckStatus::Skip),
+        );
+        let time = seconds(self.duration_ms);
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites name=\"cosmian_vm verify\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{time}\">\n"
+        ));
+        xml.push_str(&format!(
+            "  <testsuite name=\"cosmian_vm verify\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{time}\">\n"
+        ));
+        xml.push_str(&format!(
+            "    <properties>\n      <property name=\"nonce\" value=\"{}\"/>\n    </properties>\n",
+            self.nonce
+        ));
+
+        for check in &self.checks {
+            xml.push_str(&format!(
+                "    <testcase classname=\"cosmian_vm.verify.{}\" name=\"{}\" time=\"{}\">\n",
+                check.kind,
+                escape_xml(&check.name),
+                seconds(check.duration_ms)
+            ));
+            let details = escape_xml(&check.details);
+            match check.status {
+                CheckStatus::Ok => (),
+                CheckStatus::Fail => xml.push_str(&format!(
+                    "      <failure message=\"{details}\" type=\"{}\"/>\n",
+                    check.kind
+                )),
+                CheckStatus::Skip => {
+                    xml.push_str(&format!("      <skipped message=\"{details}\"/>\n"));
+                }
+            }
+            if check.kind == CheckKind::ImaIntegrity && !self.unknown_ima_entries.is_empty() {
+                xml.push_str("      <system-out>");
+                for entry in &self.unknown_ima_entries {
+                    xml.push_str(&format!(
+                        "{} {}:{}\n",
+                        escape_xml(&entry.path),
+                        entry.hash_method,
+                        entry.hash
+                    ));
+                }
+                xml.push_str("</system-out>\n");
+            }
+            xml.push_str("    </testcase>\n");
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>");
+        xml
+    }
+}
+
+fn duration_ms(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+fn seconds(duration_ms: u64) -> String {
+    format!("{}.{:03}", duration_ms / 1000, duration_ms % 1000)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
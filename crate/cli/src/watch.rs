@@ -0,0 +1,261 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use cosmian_vm_client::client::CosmianVmClient;
+use serde::Serialize;
+use tokio::{task::spawn_blocking, time::MissedTickBehavior};
+
+use crate::verify::{
+    load_tpm_ek_ca,
+    report::{ReportFormat, VerifyReport},
+    ImaState, Reference, Verification,
+};
+
+/// The output format of the watch events
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum WatchFormat {
+    /// One line per agent and per round
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Verify periodically one or several Cosmian VMs and raise an alert when one drifts
+///
+/// Only the IMA entries added since the previous round are compared to the snapshot
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Path of the Cosmian VM snapshot
+    ///
+    /// Repeat it to verify against the union of several snapshots of VMs created from the same image
+    #[arg(short, long, required = true)]
+    snapshot: Vec<PathBuf>,
+
+    /// Path of the whitelist (TOML) of the files allowed even if they are not in the snapshot
+    #[arg(short, long)]
+    whitelist: Option<PathBuf>,
+
+    /// URL of a Cosmian VM Agent to watch (repeat it to watch several VMs, default to `--url`)
+    #[arg(long)]
+    agent: Vec<String>,
+
+    /// Number of seconds between two verifications of an agent
+    #[arg(long, default_value_t = 300)]
+    interval: u64,
+
+    /// Format of the events printed on stdout
+    #[arg(long, value_enum, default_value_t)]
+    output: WatchFormat,
+
+    /// Shell command run on each alert, with the alert (JSON) on its standard input
+    #[arg(long)]
+    alert_command: Option<String>,
+
+    /// Path of the TPM manufacturer CA certificates (PEM) the EK certificate must be issued by
+    #[arg(long)]
+    tpm_ek_ca: Option<PathBuf>,
+}
+
+/// The status of an agent after a verification round
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WatchStatus {
+    Ok,
+    /// The agent can't be reached
+    Unreachable,
+    /// A check failed
+    Drift,
+}
+
+/// The result of the verification of an agent in a round
+#[derive(Debug, Serialize)]
+struct WatchEvent<'a> {
+    timestamp: u64,
+    agent: &'a str,
+    status: WatchStatus,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<&'a VerifyReport>,
+}
+
+/// A watched agent with the IMA entries already verified
+struct WatchedAgent {
+    url: String,
+    client: Option<CosmianVmClient>,
+    ima_state: ImaState,
+}
+
+impl WatchArgs {
+    pub async fn run(&self, url: Option<&str>, allow_insecure_tls: bool) -> Result<()> {
+        let urls = match (self.agent.is_empty(), url) {
+            (false, _) => self.agent.clone(),
+            (true, Some(url)) => vec![url.to_owned()],
+            (true, None) => anyhow::bail!("No agent to watch: use --agent or --url"),
+        };
+
+        let reference = Reference::load(&self.snapshot, self.whitelist.as_deref())?;
+        let tpm_ek_ca = self.tpm_ek_ca.as_deref().map(load_tpm_ek_ca).transpose()?;
+        let verification = Verification {
+            reference: &reference,
+            applications: &[],
+            keep_going: true,
+            tpm_ek_ca: tpm_ek_ca.as_deref(),
+        };
+
+        let mut agents = urls
+            .into_iter()
+            .map(|url| WatchedAgent {
+                url,
+                client: None,
+                ima_state: ImaState::default(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut interval = tokio::time::interval(Duration::from_secs(self.interval));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            for agent in &mut agents {
+                self.watch(agent, &verification, allow_insecure_tls).await?;
+            }
+        }
+    }
+
+    /// Verify an agent and emit the resulting event
+    async fn watch(
+        &self,
+        agent: &mut WatchedAgent,
+        verification: &Verification<'_>,
+        allow_insecure_tls: bool,
+    ) -> Result<()> {
+        // The client is instantiated again after a failure to fetch the agent certificate
+        if agent.client.is_none() {
+            match CosmianVmClient::instantiate(
+                &agent.url,
+                env!("CARGO_PKG_VERSION"),
+                allow_insecure_tls,
+            ) {
+                Ok(client) => agent.client = Some(client),
+                Err(e) => {
+                    return self.emit(&WatchEvent {
+                        timestamp: now(),
+                        agent: &agent.url,
+                        status: WatchStatus::Unreachable,
+                        message: e.to_string(),
+                        report: None,
+                    });
+                }
+            }
+        }
+        let Some(client) = &agent.client else {
+            return Ok(());
+        };
+
+        let verified_entries = agent.ima_state.verified_entries();
+        let mut report = VerifyReport::new(&[0; 32], ReportFormat::Json);
+        verification
+            .run(client, &mut report, &mut agent.ima_state)
+            .await;
+        report.close();
+
+        let event = match report.first_failure() {
+            None => WatchEvent {
+                timestamp: now(),
+                agent: &agent.url,
+                status: WatchStatus::Ok,
+                message: format!(
+                    "{} new IMA entries verified",
+                    agent
+                        .ima_state
+                        .verified_entries()
+                        .saturating_sub(verified_entries)
+                ),
+                report: None,
+            },
+            Some(_) => WatchEvent {
+                timestamp: now(),
+                agent: &agent.url,
+                status: WatchStatus::Drift,
+                message: report
+                    .failures()
+                    .map(|check| format!("{}: {}", check.kind, check.details))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                report: Some(&report),
+            },
+        };
+
+        // The certificate may have been renewed: fetch it again on the next round
+        if event.status == WatchStatus::Drift {
+            agent.client = None;
+        }
+
+        self.emit(&event)
+    }
+
+    /// Print the event and run the alert command if the agent is not ok
+    fn emit(&self, event: &WatchEvent) -> Result<()> {
+        match self.output {
+            WatchFormat::Text => match event.status {
+                WatchStatus::Ok => println!("[ OK ] {}: {}", event.agent, event.message),
+                WatchStatus::Unreachable | WatchStatus::Drift => {
+                    println!("[ ALERT ] {}: {}", event.agent, event.message);
+                }
+            },
+            WatchFormat::Json => println!("{}", serde_json::to_string(event)?),
+        }
+
+        if event.status != WatchStatus::Ok {
+            if let Some(command) = &self.alert_command {
+                run_alert_command(
+                    command.clone(),
+                    event.agent.to_owned(),
+                    serde_json::to_vec(event)?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the alert command in the background
+///
+/// A failure of the command is logged and doesn't stop the watch
+fn run_alert_command(command: String, agent: String, alert: Vec<u8>) {
+    spawn_blocking(move || {
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("COSMIAN_VM_AGENT", &agent)
+            .stdin(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(&alert)?;
+                }
+                child.wait()
+            });
+
+        match result {
+            Ok(status) if status.success() => (),
+            Ok(status) => tracing::warn!("Alert command for {agent} failed: {status}"),
+            Err(e) => tracing::warn!("Can't run the alert command for {agent}: {e}"),
+        }
+    });
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
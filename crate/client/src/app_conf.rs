@@ -0,0 +1,134 @@
+//! The encryption of the app configuration for the ephemeral key of the agent
+//!
+//! The agent generates an ephemeral P-256 key whose public key is bound into a fresh TEE
+//! quote (see `app_key_binding_nonce`). Once this quote is verified, the configuration is
+//! encrypted for this key (ECDH with an ephemeral key of the client, HKDF-SHA256 and
+//! AES-256-GCM): only the agent running in the verified confidential VM can decrypt it
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm,
+};
+use p256::{
+    ecdh::{EphemeralSecret, SharedSecret},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey,
+};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::{client::EncryptedAppConf, error::Error};
+
+/// The HKDF info prefix, followed by the public keys of the agent and of the client
+const HKDF_INFO: &[u8] = b"cosmian_vm app conf";
+
+/// The size of the AES-GCM nonce
+const NONCE_SIZE: usize = 12;
+
+/// The public key (SEC1 uncompressed point) of the ephemeral key `secret` of the agent
+#[must_use]
+pub fn public_key(secret: &EphemeralSecret) -> Vec<u8> {
+    secret
+        .public_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec()
+}
+
+/// Encrypt the app configuration `content` for the ephemeral public key `agent_public_key`
+/// (SEC1 point) the agent generated for the nonce `key_nonce`
+pub fn encrypt(
+    key_nonce: &[u8],
+    agent_public_key: &[u8],
+    content: &[u8],
+) -> Result<EncryptedAppConf, Error> {
+    let agent_key = PublicKey::from_sec1_bytes(agent_public_key)
+        .map_err(|e| Error::Cryptography(format!("Invalid agent public key: {e}")))?;
+
+    let client_secret = EphemeralSecret::random(&mut OsRng);
+    let public_key = public_key(&client_secret);
+    let cipher = cipher(
+        &client_secret.diffie_hellman(&agent_key),
+        agent_public_key,
+        &public_key,
+    )?;
+
+    let mut nonce = [0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(&nonce.into(), content)
+        .map_err(|e| Error::Cryptography(format!("Can't encrypt the app configuration: {e}")))?;
+
+    Ok(EncryptedAppConf {
+        key_nonce: key_nonce.to_vec(),
+        public_key,
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+/// Decrypt the app configuration `conf` encrypted for the ephemeral key `agent_secret`
+pub fn decrypt(agent_secret: &EphemeralSecret, conf: &EncryptedAppConf) -> Result<Vec<u8>, Error> {
+    let client_key = PublicKey::from_sec1_bytes(&conf.public_key)
+        .map_err(|e| Error::Cryptography(format!("Invalid client public key: {e}")))?;
+    let nonce: [u8; NONCE_SIZE] =
+        conf.nonce.as_slice().try_into().map_err(|_| {
+            Error::Cryptography(format!("The nonce should be {NONCE_SIZE} bytes long"))
+        })?;
+
+    let cipher = cipher(
+        &agent_secret.diffie_hellman(&client_key),
+        &public_key(agent_secret),
+        &conf.public_key,
+    )?;
+    cipher
+        .decrypt(&nonce.into(), conf.ciphertext.as_slice())
+        .map_err(|_| {
+            Error::Cryptography(
+                "Can't decrypt the app configuration: not encrypted for the current key".to_owned(),
+            )
+        })
+}
+
+/// The AES-256-GCM cipher keyed by the shared secret, bound to both public keys
+fn cipher(
+    shared_secret: &SharedSecret,
+    agent_public_key: &[u8],
+    client_public_key: &[u8],
+) -> Result<Aes256Gcm, Error> {
+    let mut key = [0; 32];
+    shared_secret
+        .extract::<Sha256>(None)
+        .expand_multi_info(&[HKDF_INFO, agent_public_key, client_public_key], &mut key)
+        .map_err(|e| Error::Cryptography(format!("Can't derive the encryption key: {e}")))?;
+
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdh::EphemeralSecret;
+    use rand::rngs::OsRng;
+
+    use super::{decrypt, encrypt, public_key};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let agent_secret = EphemeralSecret::random(&mut OsRng);
+        let content = b"{\"password\": \"secret\"}";
+
+        let mut conf = encrypt(&[1; 32], &public_key(&agent_secret), content).unwrap();
+        assert_ne!(conf.ciphertext.as_slice(), content.as_slice());
+        assert_eq!(decrypt(&agent_secret, &conf).unwrap(), content);
+
+        // Only the key it is encrypted for can decrypt it
+        let other_secret = EphemeralSecret::random(&mut OsRng);
+        assert!(decrypt(&other_secret, &conf).is_err());
+
+        // A tampered configuration is rejected
+        conf.ciphertext[0] ^= 1;
+        assert!(decrypt(&agent_secret, &conf).is_err());
+
+        assert!(encrypt(&[1; 32], &[4; 65], content).is_err());
+    }
+}
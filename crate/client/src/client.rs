@@ -4,13 +4,16 @@ use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::{Client, ClientBuilder, Response, StatusCode, Url};
 use rustls::{client::WebPkiVerifier, Certificate};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tpm_quote::PcrHashMethod;
 
 use crate::{
     certificate_verifier::{LeafCertificateVerifier, NoVerifier},
+    envelope::SnapshotEnvelope,
     error::Error,
+    pcr::{PcrBank, PcrSelection, PcrValues},
     ser_de::base64_serde,
-    snapshot::CosmianVmSnapshot,
+    snapshot::{CosmianVmSnapshot, SnapshotStatus},
 };
 
 #[derive(Clone)]
@@ -29,23 +32,202 @@ pub struct TpmQuoteResponse {
     pub signature: Vec<u8>,
     #[serde(with = "base64_serde")]
     pub public_key: Vec<u8>,
+    /// The values of the quoted PCRs, except the PCR extended by IMA
+    /// whose value is given by the replay of the IMA log
+    #[serde(default, skip_serializing_if = "PcrValues::is_empty")]
+    pub pcr_values: PcrValues,
+}
+
+/// The media type of the raw binary responses of the agent
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Binary data serialized as a base64 JSON string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Base64Bytes(#[serde(with = "base64_serde")] pub Vec<u8>);
+
+/// Binary data returned as JSON by the agent
+///
+/// The former agents returned an array of numbers instead of a base64 string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonBytes {
+    Base64(Base64Bytes),
+    Array(Vec<u8>),
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct QuoteParam {
     #[serde(with = "base64_serde")]
     pub nonce: Vec<u8>,
+    /// The PCRs quoted by the TPM in addition to the PCR extended by IMA
+    /// (ignored by the TEE quote)
+    #[serde(default, skip_serializing_if = "PcrSelection::is_empty")]
+    pub pcrs: PcrSelection,
+    /// The PCR bank quoted by the TPM (default to the first bank configured in the agent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_bank: Option<PcrBank>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ImaBinaryParam {
+    /// Return only the entries from this entry number
+    pub from_entry: Option<usize>,
+    /// The PCR bank of the returned PCR value (default to the first bank configured in the agent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_bank: Option<PcrBank>,
+}
+
+/// The IMA entries added after the first `from_entry` entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImaBinaryResponse {
+    pub from_entry: usize,
+    /// The PCR value extended by IMA after the first `from_entry` entries
+    #[serde(with = "base64_serde")]
+    pub pcr_value: Vec<u8>,
+    pub pcr_value_hash_method: PcrHashMethod,
+    /// The entries from `from_entry` (binary format)
+    #[serde(with = "base64_serde")]
+    pub entries: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AttestationParam {
+    #[serde(with = "base64_serde")]
+    pub nonce: Vec<u8>,
+    /// Return only the IMA entries from this entry number
+    pub from_entry: Option<usize>,
+    /// The PCRs quoted by the TPM in addition to the PCR extended by IMA
+    #[serde(default, skip_serializing_if = "PcrSelection::is_empty")]
+    pub pcrs: PcrSelection,
+    /// The PCR bank quoted by the TPM (default to the first bank configured in the agent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_bank: Option<PcrBank>,
+}
+
+/// The TEE quote, the TPM quote and the IMA log taken at once with the same nonce
+///
+/// The IMA entries are the ones extended in the PCR quoted by the TPM.
+/// The report data of the TEE quote binds the TPM attestation key with the nonce
+/// (see `tpm_key_binding_nonce`), which ties the TPM to the confidential VM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(with = "base64_serde")]
+    pub tee_quote: Vec<u8>,
+    pub tpm_quote: TpmQuoteResponse,
+    pub ima: ImaBinaryResponse,
+}
+
+/// The nonce given to forge the report data of the TEE quote of an `AttestationResponse`
+///
+/// It binds the public key of the TPM attestation key: `sha256(nonce || tpm_public_key)`
+#[must_use]
+pub fn tpm_key_binding_nonce(nonce: &[u8], tpm_public_key: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(nonce);
+    hasher.update(tpm_public_key);
+    hasher.finalize().into()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AppKeyParam {
+    #[serde(with = "base64_serde")]
+    pub nonce: Vec<u8>,
+}
+
+/// The ephemeral key of the agent the app configuration is encrypted for
+///
+/// The report data of the TEE quote binds its public key with the nonce
+/// (see `app_key_binding_nonce`), which ties the key to the confidential VM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppKeyResponse {
+    /// The public key (SEC1 uncompressed P-256 point)
+    #[serde(with = "base64_serde")]
+    pub public_key: Vec<u8>,
+    #[serde(with = "base64_serde")]
+    pub tee_quote: Vec<u8>,
+}
+
+/// The nonce given to forge the report data of the TEE quote of an `AppKeyResponse`
+///
+/// It binds the ephemeral public key of the agent: `sha256("app_key" || nonce || public_key)`.
+/// The prefix keeps it distinct from the binding of the TPM attestation key
+#[must_use]
+pub fn app_key_binding_nonce(nonce: &[u8], public_key: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"app_key");
+    hasher.update(nonce);
+    hasher.update(public_key);
+    hasher.finalize().into()
+}
+
+/// A credential encrypted for the TPM endorsement key and bound to the name of the
+/// attestation key (see `credential::make_credential`)
+#[derive(Serialize, Deserialize)]
+pub struct ActivateCredentialParam {
+    #[serde(with = "base64_serde")]
+    pub credential_blob: Vec<u8>,
+    #[serde(with = "base64_serde")]
+    pub encrypted_secret: Vec<u8>,
+}
+
+/// A secret to seal to the current values of PCRs of the TPM
+#[derive(Serialize, Deserialize)]
+pub struct SealParam {
+    #[serde(with = "base64_serde")]
+    pub secret: Vec<u8>,
+    /// The PCRs the secret is sealed to (default to the PCRs configured in the agent)
+    #[serde(default, skip_serializing_if = "PcrSelection::is_empty")]
+    pub pcrs: PcrSelection,
+    /// The PCR bank of the PCRs (default to the first bank configured in the agent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_bank: Option<PcrBank>,
+}
+
+/// A secret sealed by the TPM
+///
+/// Only the same TPM can unseal it, while the PCRs `pcrs` have the values they had when
+/// the secret was sealed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedSecret {
+    pub pcrs: PcrSelection,
+    pub hash_bank: PcrBank,
+    /// The public area of the sealed object (`TPMT_PUBLIC`)
+    #[serde(with = "base64_serde")]
+    pub public: Vec<u8>,
+    /// The private area of the sealed object, encrypted by the TPM
+    #[serde(with = "base64_serde")]
+    pub private: Vec<u8>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct SnapshotParam {
+    /// Hash all the files again instead of reusing the hashes of the previous snapshot
+    #[serde(default)]
+    pub full: bool,
+    /// Return the snapshot wrapped into a `SnapshotEnvelope`
+    #[serde(default)]
+    pub envelope: bool,
+    /// The PCRs whose values are recorded in the snapshot
+    /// (default to the PCRs configured in the agent)
+    #[serde(default, skip_serializing_if = "PcrSelection::is_empty")]
+    pub pcrs: PcrSelection,
 }
 
 pub const USER_AGENT_ATTRIBUTE: &str = "cli-version";
 
 impl CosmianVmClient {
     /// Proceed a snapshot of the VM
-    pub async fn get_snapshot(&self) -> Result<CosmianVmSnapshot, Error> {
+    ///
+    /// If `full` is set, the agent hashes all the files again
+    /// instead of reusing the hashes of the previous snapshot.
+    /// The values of the PCRs `pcrs` are recorded in the snapshot
+    pub async fn get_snapshot(
+        &self,
+        full: bool,
+        pcrs: &PcrSelection,
+    ) -> Result<CosmianVmSnapshot, Error> {
         loop {
-            let snapshot: Option<CosmianVmSnapshot> = self.get("/snapshot", None::<&()>).await?;
-
-            if let Some(snapshot) = snapshot {
+            if let Some(snapshot) = self.poll_snapshot(full, pcrs).await? {
                 return Ok(snapshot);
             } else {
                 // Not ready
@@ -54,6 +236,55 @@ impl CosmianVmClient {
         }
     }
 
+    /// Get the snapshot of the VM if it's ready, or order it otherwise
+    ///
+    /// Return `None` while the snapshot is processing
+    pub async fn poll_snapshot(
+        &self,
+        full: bool,
+        pcrs: &PcrSelection,
+    ) -> Result<Option<CosmianVmSnapshot>, Error> {
+        self.get(
+            "/snapshot",
+            Some(&SnapshotParam {
+                full,
+                envelope: false,
+                pcrs: pcrs.clone(),
+            }),
+        )
+        .await
+    }
+
+    /// Get the snapshot of the VM wrapped into a signed envelope if it's ready,
+    /// or order it otherwise
+    ///
+    /// Return `None` while the snapshot is processing
+    pub async fn poll_snapshot_envelope(
+        &self,
+        full: bool,
+        pcrs: &PcrSelection,
+    ) -> Result<Option<SnapshotEnvelope>, Error> {
+        self.get(
+            "/snapshot",
+            Some(&SnapshotParam {
+                full,
+                envelope: true,
+                pcrs: pcrs.clone(),
+            }),
+        )
+        .await
+    }
+
+    /// Get the progress of the snapshot being processed
+    pub async fn snapshot_status(&self) -> Result<SnapshotStatus, Error> {
+        self.get("/snapshot/status", None::<&()>).await
+    }
+
+    /// Cancel the snapshot being processed
+    pub async fn cancel_snapshot(&self) -> Result<(), Error> {
+        self.post("/snapshot/cancel", None::<&()>).await
+    }
+
     /// Proceed a snapshot of the VM
     pub async fn reset_snapshot(&self) -> Result<(), Error> {
         self.delete("/snapshot", None::<&()>).await
@@ -66,42 +297,155 @@ impl CosmianVmClient {
 
     /// Get the IMA list as a binary blob
     pub async fn ima_binary(&self) -> Result<Vec<u8>, Error> {
-        self.get("/ima/binary", None::<&()>).await
+        self.get_bytes("/ima/binary", None::<&()>).await
+    }
+
+    /// Get the IMA entries from the entry number `from_entry` as a binary blob
+    ///
+    /// The PCR value after the previous entries is returned with them (in the bank `hash_bank`
+    /// or the default bank of the agent), so that only the new entries have to be replayed
+    pub async fn ima_binary_from(
+        &self,
+        from_entry: usize,
+        hash_bank: Option<PcrBank>,
+    ) -> Result<ImaBinaryResponse, Error> {
+        self.get(
+            "/ima/binary",
+            Some(&ImaBinaryParam {
+                from_entry: Some(from_entry),
+                hash_bank,
+            }),
+        )
+        .await
     }
 
     /// Get the quote of the tee
     pub async fn tee_quote(&self, nonce: &[u8]) -> Result<Vec<u8>, Error> {
-        self.get(
+        self.get_bytes(
             "/quote/tee",
             Some(&QuoteParam {
                 nonce: nonce.to_vec(),
+                pcrs: PcrSelection::default(),
+                hash_bank: None,
+            }),
+        )
+        .await
+    }
+
+    /// Get the TEE quote, the TPM quote and the IMA log at once, bound to the same `nonce`
+    ///
+    /// If `from_entry` is given, only the IMA entries from this entry number are returned,
+    /// unless the IMA log is shorter (e.g. the VM rebooted): the whole log is then returned
+    /// with `ima.from_entry` set to 0. The PCRs `pcrs` are quoted in addition to the PCR extended by IMA, in the bank
+    /// `hash_bank` or the default bank of the agent
+    pub async fn attest(
+        &self,
+        nonce: &[u8],
+        from_entry: Option<usize>,
+        pcrs: &PcrSelection,
+        hash_bank: Option<PcrBank>,
+    ) -> Result<AttestationResponse, Error> {
+        self.post(
+            "/attestation",
+            Some(&AttestationParam {
+                nonce: nonce.to_vec(),
+                from_entry,
+                pcrs: pcrs.clone(),
+                hash_bank,
             }),
         )
         .await
     }
 
     /// Get the quote of the tpm
-    pub async fn tpm_quote(&self, nonce: &[u8]) -> Result<TpmQuoteResponse, Error> {
+    ///
+    /// The PCRs `pcrs` are quoted in addition to the PCR extended by IMA, in the bank
+    /// `hash_bank` or the default bank of the agent
+    pub async fn tpm_quote(
+        &self,
+        nonce: &[u8],
+        pcrs: &PcrSelection,
+        hash_bank: Option<PcrBank>,
+    ) -> Result<TpmQuoteResponse, Error> {
         self.get(
             "/quote/tpm",
             Some(&QuoteParam {
                 nonce: nonce.to_vec(),
+                pcrs: pcrs.clone(),
+                hash_bank,
             }),
         )
         .await
     }
 
-    /// Initialize the deployed app
-    pub async fn init_app(&self, content: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    /// Get the certificate (DER) of the TPM endorsement key
+    pub async fn tpm_ek_certificate(&self) -> Result<Vec<u8>, Error> {
+        self.get_bytes("/tpm/ek_certificate", None::<&()>).await
+    }
+
+    /// Get the credential recovered by the TPM from the `credential_blob` and the
+    /// `encrypted_secret` made for its endorsement key and its attestation key
+    pub async fn tpm_activate_credential(
+        &self,
+        credential_blob: &[u8],
+        encrypted_secret: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let Base64Bytes(credential) = self
+            .post(
+                "/tpm/activate_credential",
+                Some(&ActivateCredentialParam {
+                    credential_blob: credential_blob.to_vec(),
+                    encrypted_secret: encrypted_secret.to_vec(),
+                }),
+            )
+            .await?;
+        Ok(credential)
+    }
+
+    /// Seal `secret` to the current values of the PCRs `pcrs` (the PCRs configured in the agent
+    /// if empty) of the bank `hash_bank`
+    ///
+    /// Only allowed from the VM itself, unless the agent is configured with an admin token
+    pub async fn seal_secret(
+        &self,
+        secret: &[u8],
+        pcrs: &PcrSelection,
+        hash_bank: Option<PcrBank>,
+    ) -> Result<SealedSecret, Error> {
         self.post(
-            "/app/init",
-            Some(&AppConf {
-                content: content.to_vec(),
+            "/secret/seal",
+            Some(&SealParam {
+                secret: secret.to_vec(),
+                pcrs: pcrs.clone(),
+                hash_bank,
             }),
         )
         .await
     }
 
+    /// Unseal a secret sealed by `seal_secret`, if the PCRs still have the same values
+    pub async fn unseal_secret(&self, sealed: &SealedSecret) -> Result<Vec<u8>, Error> {
+        let Base64Bytes(secret) = self.post("/secret/unseal", Some(sealed)).await?;
+        Ok(secret)
+    }
+
+    /// Get a new ephemeral key of the agent to encrypt the app configuration for,
+    /// with a TEE quote binding it to `nonce` (see `app_key_binding_nonce`)
+    pub async fn app_key(&self, nonce: &[u8]) -> Result<AppKeyResponse, Error> {
+        self.post(
+            "/app/key",
+            Some(&AppKeyParam {
+                nonce: nonce.to_vec(),
+            }),
+        )
+        .await
+    }
+
+    /// Initialize the deployed app with its configuration encrypted for the key of `app_key`
+    pub async fn init_app(&self, conf: &EncryptedAppConf) -> Result<(), Error> {
+        self.post("/app/init", Some(conf)).await
+    }
+
     /// Restart the deployed app
     pub async fn restart_app(&self) -> Result<(), Error> {
         self.post("/app/restart", None::<&()>).await
@@ -162,6 +506,43 @@ impl CosmianVmClient {
         Err(Error::RequestFailed(p))
     }
 
+    /// Get binary data, sent as raw bytes by the agent to avoid the JSON overhead
+    pub async fn get_bytes<O>(&self, endpoint: &str, data: Option<&O>) -> Result<Vec<u8>, Error>
+    where
+        O: Serialize,
+    {
+        let agent_url = format!("{}{endpoint}", self.agent_url);
+        let mut request = self
+            .client
+            .get(agent_url)
+            .header(reqwest::header::ACCEPT, OCTET_STREAM);
+        if let Some(d) = data {
+            request = request.query(d);
+        }
+        let response = request.send().await?;
+
+        let status_code = response.status();
+        if status_code.is_success() {
+            let is_octet_stream = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_some_and(|content_type| content_type.starts_with(OCTET_STREAM));
+
+            if is_octet_stream {
+                return Ok(response.bytes().await?.to_vec());
+            }
+
+            return Ok(match response.json::<JsonBytes>().await? {
+                JsonBytes::Base64(Base64Bytes(bytes)) | JsonBytes::Array(bytes) => bytes,
+            });
+        }
+
+        // process error
+        let p = handle_error(response).await?;
+        Err(Error::RequestFailed(p))
+    }
+
     pub async fn post<O, R>(&self, endpoint: &str, data: Option<&O>) -> Result<R, Error>
     where
         O: Serialize,
@@ -205,17 +586,27 @@ impl CosmianVmClient {
     }
 }
 
-/// Configuration of the deployed application.
+/// Configuration of the deployed application, encrypted for the ephemeral key of the agent
+/// (see `app_conf::encrypt`).
 ///
 /// This configuration depends on the app developer.
 #[derive(Serialize, Deserialize)]
-pub struct AppConf {
-    /// Raw content of the configuration.
+pub struct EncryptedAppConf {
+    /// The nonce the ephemeral key of the agent has been requested with (`POST /app/key`)
+    #[serde(with = "base64_serde")]
+    pub key_nonce: Vec<u8>,
+    /// The ephemeral public key of the client (SEC1 uncompressed point)
+    #[serde(with = "base64_serde")]
+    pub public_key: Vec<u8>,
+    /// The AES-GCM nonce
+    #[serde(with = "base64_serde")]
+    pub nonce: Vec<u8>,
+    /// Raw content of the configuration, encrypted.
     ///
     /// Note: fully depends on the app, so
     /// we can't guess better than bytes.
     #[serde(with = "base64_serde")]
-    pub content: Vec<u8>,
+    pub ciphertext: Vec<u8>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -0,0 +1,545 @@
+//! The verifier side of the TPM credential activation (`TPM2_MakeCredential`)
+//!
+//! A credential encrypted for the endorsement key (EK) and bound to the name of the
+//! attestation key (AK) can only be recovered by the TPM holding both keys: it proves that
+//! the AK signing the TPM quotes lives in the same TPM as an EK certified by its manufacturer
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes::Aes128;
+use cfb_mode::{
+    cipher::{AsyncStreamCipher, KeyIvInit},
+    Encryptor,
+};
+use hmac::{Hmac, Mac};
+use p256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint};
+use rand::{rngs::OsRng, RngCore};
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs1v15::Pkcs1v15Sign, Oaep, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use x509_cert::{
+    der::{oid::ObjectIdentifier, Encode},
+    ext::pkix::{BasicConstraints, KeyUsage},
+    Certificate,
+};
+
+use crate::error::Error;
+
+/// `TPM_ALG_SHA256`, the name algorithm of the keys created from the default TPM templates
+const TPM_ALG_SHA256: u16 = 0x000B;
+
+/// The attributes of a restricted signing key which can't leave its TPM:
+/// `fixedTPM`, `fixedParent`, `sensitiveDataOrigin`, `restricted` and `sign`
+const AK_ATTRIBUTES: u32 = 0x0000_0002 | 0x0000_0010 | 0x0000_0020 | 0x0001_0000 | 0x0004_0000;
+
+const ID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+
+/// The maximum number of CA certificates from an EK certificate to its root CA
+const MAX_EK_CHAIN_LENGTH: usize = 8;
+
+/// The public key of a TPM endorsement key created from a default template
+/// (ECC NIST P-256 or RSA 2048, with SHA-256 and AES-128-CFB)
+#[derive(Clone, Debug)]
+pub enum EkPublicKey {
+    Ecc(p256::PublicKey),
+    Rsa(RsaPublicKey),
+}
+
+impl EkPublicKey {
+    /// The public key certified by the EK certificate `certificate`
+    pub fn from_certificate(certificate: &Certificate) -> Result<Self, Error> {
+        let spki = &certificate.tbs_certificate.subject_public_key_info;
+        let key = spki.subject_public_key.raw_bytes();
+
+        // The RSA EK certificates may use the `id-RSAES-OAEP` algorithm identifier
+        if spki.algorithm.oid == ID_EC_PUBLIC_KEY {
+            p256::PublicKey::from_sec1_bytes(key)
+                .map(Self::Ecc)
+                .map_err(|e| Error::Tpm(format!("Unsupported ECC EK public key: {e}")))
+        } else {
+            RsaPublicKey::from_pkcs1_der(key)
+                .map(Self::Rsa)
+                .map_err(|e| Error::Tpm(format!("Unsupported RSA EK public key: {e}")))
+        }
+    }
+}
+
+/// The name of the TPM object whose public area (`TPMT_PUBLIC`) is `public`
+///
+/// The object must be a restricted signing key which can't leave its TPM
+pub fn attestation_key_name(public: &[u8]) -> Result<Vec<u8>, Error> {
+    if public.len() < 8 {
+        return Err(Error::Tpm("Truncated TPM public area".to_owned()));
+    }
+
+    let name_algorithm = u16::from_be_bytes([public[2], public[3]]);
+    if name_algorithm != TPM_ALG_SHA256 {
+        return Err(Error::Tpm(format!(
+            "Unsupported name algorithm {name_algorithm:#06x} for the TPM attestation key"
+        )));
+    }
+
+    let attributes = u32::from_be_bytes([public[4], public[5], public[6], public[7]]);
+    if attributes & AK_ATTRIBUTES != AK_ATTRIBUTES {
+        return Err(Error::Tpm(format!(
+            "The TPM attestation key is not a restricted signing key bound to its TPM \
+             (attributes: {attributes:#010x})"
+        )));
+    }
+
+    let mut name = TPM_ALG_SHA256.to_be_bytes().to_vec();
+    name.extend(Sha256::digest(public));
+    Ok(name)
+}
+
+/// Check that the EK certificate `certificate` chains up to a self-signed root among the
+/// TPM manufacturer CA certificates `ca_certificates`
+///
+/// The intermediate CA certificates must be given with the root ones. Every certificate of
+/// the chain must be valid now, and every CA certificate must be allowed to sign certificates
+/// (`basicConstraints` CA and, if present, `keyUsage` keyCertSign)
+pub fn verify_ek_certificate(
+    certificate: &Certificate,
+    ca_certificates: &[Certificate],
+) -> Result<(), Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::Unexpected(e.to_string()))?;
+    verify_ek_chain(certificate, ca_certificates, now)
+}
+
+/// Check the chain of the EK certificate `certificate` at the time `now` (since the UNIX epoch)
+fn verify_ek_chain(
+    certificate: &Certificate,
+    ca_certificates: &[Certificate],
+    now: Duration,
+) -> Result<(), Error> {
+    check_validity(certificate, now)?;
+
+    let mut current = certificate;
+    for _ in 0..MAX_EK_CHAIN_LENGTH {
+        let issuer = find_issuer(current, ca_certificates, now)?;
+        if issuer.tbs_certificate.subject == issuer.tbs_certificate.issuer
+            && verify_issued_by(issuer, issuer).is_ok()
+        {
+            return Ok(());
+        }
+        current = issuer;
+    }
+
+    Err(Error::Tpm(format!(
+        "The EK certificate doesn't chain up to a root CA in less than {MAX_EK_CHAIN_LENGTH} certificates"
+    )))
+}
+
+/// The CA certificate among `ca_certificates` which issued `certificate`
+fn find_issuer<'a>(
+    certificate: &Certificate,
+    ca_certificates: &'a [Certificate],
+    now: Duration,
+) -> Result<&'a Certificate, Error> {
+    let mut errors = vec![];
+    for ca in ca_certificates
+        .iter()
+        .filter(|ca| ca.tbs_certificate.subject == certificate.tbs_certificate.issuer)
+    {
+        match verify_issued_by(certificate, ca)
+            .and_then(|()| check_validity(ca, now))
+            .and_then(|()| check_ca(ca))
+        {
+            Ok(()) => return Ok(ca),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Err(Error::Tpm(if errors.is_empty() {
+        format!(
+            "No TPM manufacturer CA certificate issued the certificate of {} (issuer: {})",
+            certificate.tbs_certificate.subject, certificate.tbs_certificate.issuer
+        )
+    } else {
+        errors
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }))
+}
+
+/// Verify the signature of `certificate` by the key of the CA certificate `ca`
+fn verify_issued_by(certificate: &Certificate, ca: &Certificate) -> Result<(), Error> {
+    let tbs_certificate = certificate
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| Error::Tpm(format!("Invalid certificate: {e}")))?;
+
+    verify_signature(
+        ca,
+        &certificate.signature_algorithm.oid,
+        &tbs_certificate,
+        certificate.signature.raw_bytes(),
+    )
+}
+
+/// Check that `certificate` is valid at the time `now` (since the UNIX epoch)
+fn check_validity(certificate: &Certificate, now: Duration) -> Result<(), Error> {
+    let validity = &certificate.tbs_certificate.validity;
+    if now < validity.not_before.to_unix_duration() || now > validity.not_after.to_unix_duration() {
+        return Err(Error::Tpm(format!(
+            "The certificate of {} is not valid now (not before {}, not after {})",
+            certificate.tbs_certificate.subject, validity.not_before, validity.not_after
+        )));
+    }
+    Ok(())
+}
+
+/// Check that the CA certificate `ca` is allowed to sign certificates
+fn check_ca(ca: &Certificate) -> Result<(), Error> {
+    let subject = &ca.tbs_certificate.subject;
+    let invalid = |e: x509_cert::der::Error| {
+        Error::Tpm(format!(
+            "Invalid extensions in the certificate of {subject}: {e}"
+        ))
+    };
+
+    let is_ca = ca
+        .tbs_certificate
+        .get::<BasicConstraints>()
+        .map_err(invalid)?
+        .is_some_and(|(_, constraints)| constraints.ca);
+    if !is_ca {
+        return Err(Error::Tpm(format!(
+            "The certificate of {subject} is not a CA certificate"
+        )));
+    }
+
+    if let Some((_, key_usage)) = ca.tbs_certificate.get::<KeyUsage>().map_err(invalid)? {
+        if !key_usage.key_cert_sign() {
+            return Err(Error::Tpm(format!(
+                "The certificate of {subject} is not allowed to sign certificates"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify the `signature` of `message` with the public key of the certificate `ca`
+fn verify_signature(
+    ca: &Certificate,
+    algorithm: &ObjectIdentifier,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    use p256::ecdsa::signature::Verifier as _;
+
+    let key = ca
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+    let invalid = |e: String| Error::Tpm(format!("Invalid EK certificate signature: {e}"));
+
+    match *algorithm {
+        SHA256_WITH_RSA | SHA384_WITH_RSA | SHA512_WITH_RSA => {
+            let key = RsaPublicKey::from_pkcs1_der(key).map_err(|e| invalid(e.to_string()))?;
+            let (scheme, digest) = match *algorithm {
+                SHA256_WITH_RSA => (
+                    Pkcs1v15Sign::new::<Sha256>(),
+                    Sha256::digest(message).to_vec(),
+                ),
+                SHA384_WITH_RSA => (
+                    Pkcs1v15Sign::new::<Sha384>(),
+                    Sha384::digest(message).to_vec(),
+                ),
+                _ => (
+                    Pkcs1v15Sign::new::<Sha512>(),
+                    Sha512::digest(message).to_vec(),
+                ),
+            };
+            key.verify(scheme, &digest, signature)
+                .map_err(|e| invalid(e.to_string()))
+        }
+        ECDSA_WITH_SHA256 => p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+            .and_then(|key| key.verify(message, &p256::ecdsa::Signature::from_der(signature)?))
+            .map_err(|e| invalid(e.to_string())),
+        ECDSA_WITH_SHA384 => p384::ecdsa::VerifyingKey::from_sec1_bytes(key)
+            .and_then(|key| key.verify(message, &p384::ecdsa::Signature::from_der(signature)?))
+            .map_err(|e| invalid(e.to_string())),
+        _ => Err(Error::Tpm(format!(
+            "Unsupported signature algorithm {algorithm} for the EK certificate"
+        ))),
+    }
+}
+
+/// Encrypt the `credential` for the endorsement key `ek` and bind it to the name `name` of
+/// the attestation key, as `TPM2_MakeCredential` does
+///
+/// Return the credential blob (`TPM2B_ID_OBJECT`) and the encrypted seed
+/// (`TPM2B_ENCRYPTED_SECRET`), without their size prefix
+pub fn make_credential(
+    ek: &EkPublicKey,
+    name: &[u8],
+    credential: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    if credential.len() > Sha256::output_size() {
+        return Err(Error::Tpm(format!(
+            "The credential is too long ({} bytes)",
+            credential.len()
+        )));
+    }
+
+    let (seed, encrypted_secret) = match ek {
+        EkPublicKey::Rsa(key) => {
+            let mut seed = vec![0; Sha256::output_size()];
+            OsRng.fill_bytes(&mut seed);
+            let encrypted_secret = key
+                .encrypt(
+                    &mut OsRng,
+                    Oaep::new_with_label::<Sha256, _>("IDENTITY\0"),
+                    &seed,
+                )
+                .map_err(|e| Error::Tpm(format!("Can't encrypt the seed: {e}")))?;
+            (seed, encrypted_secret)
+        }
+        EkPublicKey::Ecc(key) => {
+            let ephemeral = EphemeralSecret::random(&mut OsRng);
+            let ephemeral_point = ephemeral.public_key().to_encoded_point(false);
+            let ek_point = key.to_encoded_point(false);
+            let (Some(x), Some(y), Some(ek_x)) =
+                (ephemeral_point.x(), ephemeral_point.y(), ek_point.x())
+            else {
+                return Err(Error::Tpm("Invalid ECC EK public key".to_owned()));
+            };
+
+            let shared_secret = ephemeral.diffie_hellman(key);
+            let seed = kdf_e(
+                shared_secret.raw_secret_bytes(),
+                b"IDENTITY",
+                x,
+                ek_x,
+                Sha256::output_size(),
+            );
+
+            // TPMS_ECC_POINT
+            let mut encrypted_secret = vec![];
+            encrypted_secret.extend(tpm2b(x));
+            encrypted_secret.extend(tpm2b(y));
+            (seed, encrypted_secret)
+        }
+    };
+
+    let symmetric_key = kdf_a(&seed, b"STORAGE", name, &[], 128);
+    let hmac_key = kdf_a(&seed, b"INTEGRITY", &[], &[], 256);
+
+    let mut encrypted_identity = tpm2b(credential);
+    Encryptor::<Aes128>::new(symmetric_key.as_slice().into(), &[0; 16].into())
+        .encrypt(&mut encrypted_identity);
+
+    let mut hmac = Hmac::<Sha256>::new_from_slice(&hmac_key)
+        .map_err(|e| Error::Tpm(format!("Invalid HMAC key: {e}")))?;
+    hmac.update(&encrypted_identity);
+    hmac.update(name);
+
+    let mut credential_blob = tpm2b(&hmac.finalize().into_bytes());
+    credential_blob.extend(encrypted_identity);
+
+    Ok((credential_blob, encrypted_secret))
+}
+
+/// A `TPM2B` buffer: `data` prefixed by its size
+fn tpm2b(data: &[u8]) -> Vec<u8> {
+    let mut buffer = (data.len() as u16).to_be_bytes().to_vec();
+    buffer.extend_from_slice(data);
+    buffer
+}
+
+/// The `KDFa` key derivation of the TPM (SP 800-108 in counter mode with HMAC-SHA256)
+fn kdf_a(key: &[u8], label: &[u8], context_u: &[u8], context_v: &[u8], bits: u32) -> Vec<u8> {
+    let size = bits as usize / 8;
+    let mut derived = Vec::with_capacity(size + Sha256::output_size());
+    let mut counter: u32 = 0;
+    while derived.len() < size {
+        counter += 1;
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+        hmac.update(&counter.to_be_bytes());
+        hmac.update(label);
+        hmac.update(&[0]);
+        hmac.update(context_u);
+        hmac.update(context_v);
+        hmac.update(&bits.to_be_bytes());
+        derived.extend(hmac.finalize().into_bytes());
+    }
+    derived.truncate(size);
+    derived
+}
+
+/// The `KDFe` key derivation of the TPM (SP 800-56A concatenation KDF with SHA256)
+fn kdf_e(
+    shared_secret: &[u8],
+    label: &[u8],
+    party_u: &[u8],
+    party_v: &[u8],
+    size: usize,
+) -> Vec<u8> {
+    let mut derived = Vec::with_capacity(size + Sha256::output_size());
+    let mut counter: u32 = 0;
+    while derived.len() < size {
+        counter += 1;
+        derived.extend(
+            Sha256::new()
+                .chain_update(counter.to_be_bytes())
+                .chain_update(shared_secret)
+                .chain_update(label)
+                .chain_update([0])
+                .chain_update(party_u)
+                .chain_update(party_v)
+                .finalize(),
+        );
+    }
+    derived.truncate(size);
+    derived
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{slice, str::FromStr, time::Duration};
+
+    use p256::{
+        ecdsa::{DerSignature, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+    use rand::rngs::OsRng;
+    use x509_cert::{
+        builder::{Builder, CertificateBuilder, Profile},
+        name::Name,
+        serial_number::SerialNumber,
+        spki::SubjectPublicKeyInfoOwned,
+        time::Validity,
+        Certificate,
+    };
+
+    use super::{attestation_key_name, kdf_a, tpm2b, verify_ek_chain};
+
+    /// A certificate of `key` for `subject` with the `profile`, signed by `signer`
+    fn certificate(
+        profile: Profile,
+        subject: &str,
+        key: &SigningKey,
+        signer: &SigningKey,
+    ) -> Certificate {
+        let public_key = key.verifying_key().to_public_key_der().unwrap();
+        CertificateBuilder::new(
+            profile,
+            SerialNumber::from(1_u32),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            Name::from_str(subject).unwrap(),
+            SubjectPublicKeyInfoOwned::try_from(public_key.as_bytes()).unwrap(),
+            signer,
+        )
+        .unwrap()
+        .build::<DerSignature>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_ek_chain() {
+        let root_key = SigningKey::random(&mut OsRng);
+        let intermediate_key = SigningKey::random(&mut OsRng);
+        let ek_key = SigningKey::random(&mut OsRng);
+        let root = certificate(Profile::Root, "CN=Root CA", &root_key, &root_key);
+        let intermediate = certificate(
+            Profile::SubCA {
+                issuer: root.tbs_certificate.subject.clone(),
+                path_len_constraint: None,
+            },
+            "CN=Intermediate CA",
+            &intermediate_key,
+            &root_key,
+        );
+        let leaf = |issuer: &Certificate, signer: &SigningKey| {
+            certificate(
+                Profile::Leaf {
+                    issuer: issuer.tbs_certificate.subject.clone(),
+                    enable_key_agreement: false,
+                    enable_key_encipherment: true,
+                    include_subject_key_identifier: false,
+                },
+                "CN=EK",
+                &ek_key,
+                signer,
+            )
+        };
+        let ek = leaf(&intermediate, &intermediate_key);
+        let now = intermediate
+            .tbs_certificate
+            .validity
+            .not_before
+            .to_unix_duration();
+
+        verify_ek_chain(&ek, &[root.clone(), intermediate.clone()], now).unwrap();
+
+        // The chain must reach a root
+        assert!(verify_ek_chain(&ek, slice::from_ref(&intermediate), now).is_err());
+        assert!(verify_ek_chain(&ek, slice::from_ref(&root), now).is_err());
+
+        // The certificates must be valid now
+        let expired = now + Duration::from_secs(7200);
+        assert!(verify_ek_chain(&ek, &[root.clone(), intermediate], expired).is_err());
+
+        // The issuer must be a CA
+        let not_ca = certificate(
+            Profile::Leaf {
+                issuer: root.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+                include_subject_key_identifier: false,
+            },
+            "CN=Intermediate CA",
+            &intermediate_key,
+            &root_key,
+        );
+        assert!(verify_ek_chain(&ek, &[root, not_ca], now).is_err());
+    }
+
+    #[test]
+    fn test_attestation_key_name() {
+        // TPMT_PUBLIC header of an ECC restricted signing key created from a default template
+        let mut public = vec![0x00, 0x23, 0x00, 0x0b, 0x00, 0x05, 0x00, 0x72];
+        public.extend([0; 16]);
+        let name = attestation_key_name(&public).unwrap();
+        assert_eq!(name.len(), 34);
+        assert_eq!(name[..2], [0x00, 0x0b]);
+
+        // A key which can be duplicated out of its TPM
+        public[7] = 0x70;
+        assert!(attestation_key_name(&public).is_err());
+
+        // A key named with SHA1
+        public[3] = 0x04;
+        public[7] = 0x72;
+        assert!(attestation_key_name(&public).is_err());
+
+        assert!(attestation_key_name(&[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_kdf_a() {
+        assert_eq!(tpm2b(&[1, 2]), vec![0, 2, 1, 2]);
+
+        let key = kdf_a(b"seed", b"STORAGE", b"name", &[], 128);
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, kdf_a(b"seed", b"STORAGE", b"name", &[], 128));
+        assert_ne!(key, kdf_a(b"seed", b"STORAGE", b"other", &[], 128));
+
+        // The derived bits are part of the derivation input
+        let long_key = kdf_a(b"seed", b"STORAGE", b"name", &[], 512);
+        assert_eq!(long_key.len(), 64);
+        assert_ne!(long_key[..16], key[..]);
+    }
+}
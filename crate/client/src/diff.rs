@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use tee_attestation::TeePolicy;
+use tpm_quote::policy::TpmPolicy;
+
+use crate::{
+    cloud_provider::CloudProvider,
+    pcr::PcrValues,
+    snapshot::{CosmianVmSnapshot, SnapshotFiles},
+};
+
+/// A value which differs between two snapshots
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    /// Return `None` if `old` and `new` are equal
+    fn from(old: T, new: T) -> Option<Self> {
+        (old != new).then_some(Self { old, new })
+    }
+}
+
+/// A file with its hashes (hex encoded)
+///
+/// A file may have several hashes in a snapshot: the IMA log records each version of a file
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct FileHashes {
+    pub path: String,
+    pub hashes: Vec<String>,
+}
+
+/// A file present in both snapshots with different hashes
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ChangedFile {
+    pub path: String,
+    pub old_hashes: Vec<String>,
+    pub new_hashes: Vec<String>,
+}
+
+/// The differences between the files of two snapshots (sorted by path)
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SnapshotFilesDiff {
+    pub added: Vec<FileHashes>,
+    pub removed: Vec<FileHashes>,
+    pub changed: Vec<ChangedFile>,
+}
+
+impl SnapshotFilesDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences between two snapshots
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub cloud_type: Option<Change<Option<CloudProvider>>>,
+    pub tee_policy: Option<Change<TeePolicy>>,
+    pub tpm_policy: Option<Change<Option<TpmPolicy>>>,
+    pub pcr_values: Option<Change<Option<PcrValues>>>,
+    pub files: SnapshotFilesDiff,
+}
+
+impl SnapshotDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.cloud_type.is_none()
+            && self.tee_policy.is_none()
+            && self.tpm_policy.is_none()
+            && self.pcr_values.is_none()
+            && self.files.is_empty()
+    }
+}
+
+impl SnapshotFiles {
+    /// Index the hashes (hex encoded) of the files by path
+    fn by_path(&self) -> BTreeMap<&str, BTreeSet<String>> {
+        let mut files = BTreeMap::<&str, BTreeSet<String>>::new();
+        for (path, hash) in &self.0 {
+            files
+                .entry(path.as_str())
+                .or_default()
+                .insert(hex::encode(hash));
+        }
+        files
+    }
+
+    /// Compute the files added, removed or changed in `new` compared to `self`
+    #[must_use]
+    pub fn diff(&self, new: &Self) -> SnapshotFilesDiff {
+        let old = self.by_path();
+        let new = new.by_path();
+
+        let mut diff = SnapshotFilesDiff::default();
+
+        for (path, old_hashes) in &old {
+            match new.get(path) {
+                None => diff.removed.push(FileHashes {
+                    path: (*path).to_owned(),
+                    hashes: old_hashes.iter().cloned().collect(),
+                }),
+                Some(new_hashes) if new_hashes != old_hashes => diff.changed.push(ChangedFile {
+                    path: (*path).to_owned(),
+                    old_hashes: old_hashes.iter().cloned().collect(),
+                    new_hashes: new_hashes.iter().cloned().collect(),
+                }),
+                Some(_) => (),
+            }
+        }
+
+        for (path, new_hashes) in &new {
+            if !old.contains_key(path) {
+                diff.added.push(FileHashes {
+                    path: (*path).to_owned(),
+                    hashes: new_hashes.iter().cloned().collect(),
+                });
+            }
+        }
+
+        diff
+    }
+}
+
+impl CosmianVmSnapshot {
+    /// Compute the differences between `self` and a `new` snapshot
+    ///
+    /// A snapshot without files is compared as an empty list of files
+    #[must_use]
+    pub fn diff(&self, new: &Self) -> SnapshotDiff {
+        let empty = SnapshotFiles(Default::default());
+
+        SnapshotDiff {
+            cloud_type: Change::from(self.cloud_type, new.cloud_type),
+            tee_policy: Change::from(self.tee_policy.clone(), new.tee_policy.clone()),
+            tpm_policy: Change::from(self.tpm_policy.clone(), new.tpm_policy.clone()),
+            pcr_values: Change::from(self.pcr_values.clone(), new.pcr_values.clone()),
+            files: self
+                .filehashes
+                .as_ref()
+                .unwrap_or(&empty)
+                .diff(new.filehashes.as_ref().unwrap_or(&empty)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::snapshot::SnapshotFiles;
+
+    use super::{ChangedFile, FileHashes};
+
+    #[test]
+    fn test_snapshot_files_diff() {
+        let old = SnapshotFiles(HashSet::from([
+            ("/usr/bin/ls".to_owned(), vec![1]),
+            ("/usr/bin/cat".to_owned(), vec![2]),
+            ("/usr/bin/rm".to_owned(), vec![3]),
+            ("/usr/bin/rm".to_owned(), vec![4]),
+        ]));
+        let new = SnapshotFiles(HashSet::from([
+            ("/usr/bin/ls".to_owned(), vec![1]),
+            ("/usr/bin/cat".to_owned(), vec![5]),
+            ("/usr/bin/rm".to_owned(), vec![4]),
+            ("/usr/bin/cp".to_owned(), vec![6]),
+        ]));
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            vec![FileHashes {
+                path: "/usr/bin/cp".to_owned(),
+                hashes: vec!["06".to_owned()]
+            }]
+        );
+        assert!(diff.removed.is_empty());
+        assert_eq!(
+            diff.changed,
+            vec![
+                ChangedFile {
+                    path: "/usr/bin/cat".to_owned(),
+                    old_hashes: vec!["02".to_owned()],
+                    new_hashes: vec!["05".to_owned()]
+                },
+                ChangedFile {
+                    path: "/usr/bin/rm".to_owned(),
+                    old_hashes: vec!["03".to_owned(), "04".to_owned()],
+                    new_hashes: vec!["04".to_owned()]
+                }
+            ]
+        );
+
+        assert!(old.diff(&old).is_empty());
+
+        let removed = new.diff(&old);
+        assert_eq!(removed.removed.len(), 1);
+        assert_eq!(removed.removed[0].path, "/usr/bin/cp");
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fmt, str::FromStr};
+
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x509_cert::{der::Decode, Certificate};
+
+use crate::{
+    cloud_provider::CloudProvider, error::Error, ser_de::base64_serde, snapshot::CosmianVmSnapshot,
+};
+
+/// The version of the `SnapshotEnvelope` format produced by this crate
+pub const SNAPSHOT_ENVELOPE_VERSION: u32 = 2;
+
+/// The header of a snapshot file in the binary format
+pub const SNAPSHOT_BINARY_MAGIC: &[u8] = b"CVMSNAP\x01";
+
+/// The zstd compression level of the binary format
+const SNAPSHOT_BINARY_COMPRESSION_LEVEL: i32 = 9;
+
+/// A snapshot with the proof of its provenance
+///
+/// The TEE quote is generated by the Cosmian VM which produced the snapshot.
+/// Its report data binds the hash of the envelope body (see `body_hash`)
+/// and the agent certificate.
+/// The hash of the envelope body is also signed by the agent TLS key, so the provenance
+/// can be checked against the agent certificate where the quote can't bind the body
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotEnvelope {
+    pub version: u32,
+    /// The date of the snapshot (seconds since the UNIX epoch)
+    pub created_at: u64,
+    /// The version of the agent which computed the snapshot
+    pub agent_version: String,
+    /// The hostname of the Cosmian VM
+    pub hostname: String,
+    /// The agent TLS certificate (DER)
+    #[serde(with = "base64_serde")]
+    pub certificate: Vec<u8>,
+    /// The SHA-256 of `certificate` (hex encoded)
+    pub certificate_fingerprint: String,
+    #[serde(with = "base64_serde")]
+    pub tee_quote: Vec<u8>,
+    /// The ECDSA signature (DER) of `body_hash` by the agent TLS key
+    ///
+    /// Empty for the envelopes produced before version 2
+    #[serde(default, with = "base64_serde")]
+    pub signature: Vec<u8>,
+    pub snapshot: CosmianVmSnapshot,
+}
+
+/// The part of the envelope bound by the TEE quote and signed by the agent
+#[derive(Serialize)]
+struct SnapshotEnvelopeBody<'a> {
+    version: u32,
+    created_at: u64,
+    agent_version: &'a str,
+    hostname: &'a str,
+    certificate_fingerprint: &'a str,
+    snapshot: &'a CosmianVmSnapshot,
+}
+
+impl SnapshotEnvelope {
+    /// The SHA-256 of the JSON serialization of the envelope body
+    /// (all the fields except the certificate, the TEE quote and the signature)
+    pub fn body_hash(&self) -> Result<[u8; 32], Error> {
+        let body = serde_json::to_vec(&SnapshotEnvelopeBody {
+            version: self.version,
+            created_at: self.created_at,
+            agent_version: &self.agent_version,
+            hostname: &self.hostname,
+            certificate_fingerprint: &self.certificate_fingerprint,
+            snapshot: &self.snapshot,
+        })
+        .map_err(|e| Error::Unexpected(format!("Can't serialize the envelope body: {e}")))?;
+
+        Ok(Sha256::digest(body).into())
+    }
+
+    /// The nonce to forge the report data of the TEE quote with
+    ///
+    /// The report data can't be freely set on Microsoft Azure and Amazon AWS:
+    /// the nonce is zeroed and the quote does not bind the envelope body
+    pub fn report_data_nonce(&self) -> Result<[u8; 32], Error> {
+        if self.is_body_bound() {
+            self.body_hash()
+        } else {
+            Ok([0; 32])
+        }
+    }
+
+    /// Whether the TEE quote binds the envelope body
+    #[must_use]
+    pub fn is_body_bound(&self) -> bool {
+        !matches!(
+            self.snapshot.cloud_type,
+            Some(CloudProvider::Azure | CloudProvider::AWS)
+        )
+    }
+
+    /// Verify the signature of the envelope body by the key of the agent `certificate`
+    /// embedded into the envelope
+    pub fn verify_signature(&self) -> Result<(), Error> {
+        if self.signature.is_empty() {
+            return Err(Error::Cryptography(
+                "The envelope is not signed by the agent".to_owned(),
+            ));
+        }
+
+        if certificate_fingerprint(&self.certificate) != self.certificate_fingerprint {
+            return Err(Error::Cryptography(
+                "The envelope has been signed for another agent certificate".to_owned(),
+            ));
+        }
+
+        verify_signature(&self.certificate, &self.body_hash()?, &self.signature)
+    }
+}
+
+/// Verify the ECDSA P-256 `signature` (DER) of `message` by the key of the DER `certificate`
+fn verify_signature(certificate: &[u8], message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let certificate = Certificate::from_der(certificate)
+        .map_err(|e| Error::Cryptography(format!("Malformed agent certificate: {e}")))?;
+    let public_key = VerifyingKey::from_sec1_bytes(
+        certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .subject_public_key
+            .raw_bytes(),
+    )
+    .map_err(|_| Error::Cryptography("The agent certificate key is not a P-256 key".to_owned()))?;
+    let signature = Signature::from_der(signature)
+        .map_err(|e| Error::Cryptography(format!("Malformed envelope signature: {e}")))?;
+
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| Error::Cryptography("The signature of the envelope is invalid".to_owned()))
+}
+
+/// The SHA-256 of a DER certificate (hex encoded)
+#[must_use]
+pub fn certificate_fingerprint(certificate: &[u8]) -> String {
+    hex::encode(Sha256::digest(certificate))
+}
+
+/// The content of a snapshot file: an envelope or a bare snapshot
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SnapshotFile {
+    Envelope(Box<SnapshotEnvelope>),
+    Snapshot(Box<CosmianVmSnapshot>),
+}
+
+/// The encodings of a snapshot file
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SnapshotFormat {
+    #[default]
+    Json,
+    /// zstd-compressed CBOR (paths of the files prefix-deduplicated)
+    Binary,
+}
+
+impl SnapshotFormat {
+    /// Detect the format of the content of a snapshot file
+    #[must_use]
+    pub fn detect(content: &[u8]) -> Self {
+        if content.starts_with(SNAPSHOT_BINARY_MAGIC) {
+            Self::Binary
+        } else {
+            Self::Json
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(Self::Json),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!(
+                "Unknown snapshot format '{format}' (expecting 'json' or 'binary')"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Binary => "binary",
+        })
+    }
+}
+
+/// The binary format can't rely on `serde(untagged)` which serializes as a human readable format
+#[derive(Serialize)]
+enum TaggedSnapshotFileRef<'a> {
+    Envelope(&'a SnapshotEnvelope),
+    Snapshot(&'a CosmianVmSnapshot),
+}
+
+#[derive(Deserialize)]
+enum TaggedSnapshotFile {
+    Envelope(Box<SnapshotEnvelope>),
+    Snapshot(Box<CosmianVmSnapshot>),
+}
+
+impl SnapshotFile {
+    /// Parse the content of a snapshot file (the format is auto-detected)
+    pub fn from_bytes(content: &[u8]) -> Result<Self, Error> {
+        match SnapshotFormat::detect(content) {
+            SnapshotFormat::Json => serde_json::from_slice(content)
+                .map_err(|e| Error::Serialization(format!("Can't parse the JSON snapshot: {e}"))),
+            SnapshotFormat::Binary => {
+                let decoder = zstd::Decoder::new(&content[SNAPSHOT_BINARY_MAGIC.len()..])?;
+                let file: TaggedSnapshotFile = ciborium::from_reader(decoder).map_err(|e| {
+                    Error::Serialization(format!("Can't parse the binary snapshot: {e}"))
+                })?;
+                Ok(match file {
+                    TaggedSnapshotFile::Envelope(envelope) => Self::Envelope(envelope),
+                    TaggedSnapshotFile::Snapshot(snapshot) => Self::Snapshot(snapshot),
+                })
+            }
+        }
+    }
+
+    /// Encode the snapshot file with the given `format`
+    pub fn to_bytes(&self, format: SnapshotFormat) -> Result<Vec<u8>, Error> {
+        match format {
+            SnapshotFormat::Json => serde_json::to_vec(self).map_err(|e| {
+                Error::Serialization(format!("Can't serialize the JSON snapshot: {e}"))
+            }),
+            SnapshotFormat::Binary => {
+                let file = match self {
+                    Self::Envelope(envelope) => TaggedSnapshotFileRef::Envelope(envelope),
+                    Self::Snapshot(snapshot) => TaggedSnapshotFileRef::Snapshot(snapshot),
+                };
+                let mut encoder = zstd::Encoder::new(
+                    SNAPSHOT_BINARY_MAGIC.to_vec(),
+                    SNAPSHOT_BINARY_COMPRESSION_LEVEL,
+                )?;
+                ciborium::into_writer(&file, &mut encoder).map_err(|e| {
+                    Error::Serialization(format!("Can't serialize the binary snapshot: {e}"))
+                })?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn snapshot(&self) -> &CosmianVmSnapshot {
+        match self {
+            Self::Envelope(envelope) => &envelope.snapshot,
+            Self::Snapshot(snapshot) => snapshot,
+        }
+    }
+
+    #[must_use]
+    pub fn into_snapshot(self) -> CosmianVmSnapshot {
+        match self {
+            Self::Envelope(envelope) => envelope.snapshot,
+            Self::Snapshot(snapshot) => *snapshot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use p256::{
+        ecdsa::{signature::Signer, DerSignature, Signature, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+    use rand::rngs::OsRng;
+    use x509_cert::{
+        builder::{Builder, CertificateBuilder, Profile},
+        der::Encode,
+        name::Name,
+        serial_number::SerialNumber,
+        spki::SubjectPublicKeyInfoOwned,
+        time::Validity,
+    };
+
+    use super::verify_signature;
+
+    /// A self-signed certificate (DER) of the P-256 `signer`
+    fn certificate(signer: &SigningKey) -> Vec<u8> {
+        let public_key = signer.verifying_key().to_public_key_der().unwrap();
+        CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(1_u32),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            Name::from_str("CN=cosmian_vm").unwrap(),
+            SubjectPublicKeyInfoOwned::try_from(public_key.as_bytes()).unwrap(),
+            signer,
+        )
+        .unwrap()
+        .build::<DerSignature>()
+        .unwrap()
+        .to_der()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let signer = SigningKey::random(&mut OsRng);
+        let certificate = certificate(&signer);
+        let signature: Signature = signer.sign(b"body hash");
+        let signature = signature.to_der().to_bytes();
+
+        verify_signature(&certificate, b"body hash", &signature).unwrap();
+
+        // Another message
+        assert!(verify_signature(&certificate, b"other hash", &signature).is_err());
+
+        // Signed by another key
+        let other_certificate = self::certificate(&SigningKey::random(&mut OsRng));
+        assert!(verify_signature(&other_certificate, b"body hash", &signature).is_err());
+
+        assert!(verify_signature(&certificate, b"body hash", &[]).is_err());
+    }
+}
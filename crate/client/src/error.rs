@@ -8,6 +8,8 @@ pub enum Error {
     Connection,
     #[error("{0}")]
     Default(String),
+    #[error("Cryptography Error: {0}")]
+    Cryptography(String),
     #[error("DNSName Error")]
     DNSName,
     #[error(transparent)]
@@ -22,8 +24,12 @@ pub enum Error {
     Reqwest(#[from] reqwest::Error),
     #[error("REST Response Failed: {0}")]
     ResponseFailed(String),
+    #[error("Serialization Error: {0}")]
+    Serialization(String),
     #[error("ServerCertificate Error")]
     ServerCertificate,
+    #[error("TPM Error: {0}")]
+    Tpm(String),
     #[error("Unexpected Error: {0}")]
     Unexpected(String),
     #[error(transparent)]
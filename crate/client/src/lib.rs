@@ -1,6 +1,12 @@
+pub mod app_conf;
 pub mod client;
 pub mod cloud_provider;
+pub mod credential;
+pub mod diff;
+pub mod envelope;
 pub mod error;
+pub mod merge;
+pub mod pcr;
 pub mod ser_de;
 pub mod snapshot;
 
@@ -0,0 +1,233 @@
+use std::fmt;
+
+use crate::{
+    error::Error,
+    snapshot::{CosmianVmSnapshot, SnapshotFiles},
+};
+
+/// A field which differs between the merged snapshots
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergeConflict {
+    CloudType,
+    TeePolicy,
+    TpmPolicy,
+    PcrValues,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::CloudType => "cloud_type",
+            Self::TeePolicy => "tee_policy",
+            Self::TpmPolicy => "tpm_policy",
+            Self::PcrValues => "pcr_values",
+        })
+    }
+}
+
+/// The result of a merge of several snapshots
+#[derive(Clone, Debug)]
+pub struct MergedSnapshot {
+    /// The union of the files of the snapshots with the policies of the first snapshot
+    pub snapshot: CosmianVmSnapshot,
+    /// The fields which differ between the snapshots (the first snapshot value has been kept)
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merge several snapshots of VMs created from the same image into a reference snapshot
+///
+/// The files of the snapshots are united. The cloud type, the policies and the PCR values
+/// must be the same in all the snapshots: otherwise they are reported in `conflicts`.
+/// The snapshot rules are kept only if all the snapshots share the same ones
+pub fn merge_snapshots(
+    snapshots: impl IntoIterator<Item = CosmianVmSnapshot>,
+) -> Result<MergedSnapshot, Error> {
+    let mut snapshots = snapshots.into_iter();
+    let mut merged = snapshots
+        .next()
+        .ok_or_else(|| Error::Default("No snapshot to merge".to_owned()))?;
+    let mut conflicts = vec![];
+
+    for snapshot in snapshots {
+        let mut conflict = |conflict| {
+            if !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+        };
+
+        if snapshot.cloud_type != merged.cloud_type {
+            conflict(MergeConflict::CloudType);
+        }
+        if snapshot.tee_policy != merged.tee_policy {
+            conflict(MergeConflict::TeePolicy);
+        }
+        if snapshot.tpm_policy != merged.tpm_policy {
+            conflict(MergeConflict::TpmPolicy);
+        }
+        if snapshot.pcr_values != merged.pcr_values || snapshot.pcr_bank != merged.pcr_bank {
+            conflict(MergeConflict::PcrValues);
+        }
+
+        merged.filehashes = match (merged.filehashes, snapshot.filehashes) {
+            (Some(SnapshotFiles(mut files)), Some(SnapshotFiles(other_files))) => {
+                files.extend(other_files);
+                Some(SnapshotFiles(files))
+            }
+            (files, None) | (None, files) => files,
+        };
+
+        if merged.rules != snapshot.rules {
+            merged.rules = None;
+        }
+
+        merged.skipped_files = match (merged.skipped_files, snapshot.skipped_files) {
+            (Some(skipped_files), Some(other_skipped_files)) => {
+                Some(skipped_files + other_skipped_files)
+            }
+            (skipped_files, None) | (None, skipped_files) => skipped_files,
+        };
+    }
+
+    Ok(MergedSnapshot {
+        snapshot: merged,
+        conflicts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tee_attestation::{SevQuoteVerificationPolicy, TeePolicy};
+    use tpm_quote::policy::TpmPolicy;
+
+    use crate::{
+        cloud_provider::CloudProvider,
+        pcr::{PcrBank, PcrValues},
+        snapshot::{CosmianVmSnapshot, SnapshotFiles, SnapshotRules},
+    };
+
+    use super::{merge_snapshots, MergeConflict};
+
+    fn snapshot(files: &[(&str, u8)]) -> CosmianVmSnapshot {
+        CosmianVmSnapshot {
+            cloud_type: Some(CloudProvider::GCP),
+            tee_policy: TeePolicy::Sev(SevQuoteVerificationPolicy::new([1; 48])),
+            tpm_policy: None,
+            filehashes: Some(SnapshotFiles(
+                files
+                    .iter()
+                    .map(|(path, hash)| ((*path).to_owned(), vec![*hash]))
+                    .collect(),
+            )),
+            rules: Some(SnapshotRules::default()),
+            skipped_files: Some(1),
+            pcr_values: Some(PcrValues::from([(0, "00".to_owned())])),
+            pcr_bank: Some(PcrBank::Sha256),
+        }
+    }
+
+    #[test]
+    fn test_merge_snapshots_files() {
+        let mut other = snapshot(&[("/usr/bin/ls", 1), ("/usr/bin/cp", 3)]);
+        other.skipped_files = Some(2);
+
+        let merged = merge_snapshots([
+            snapshot(&[("/usr/bin/ls", 1), ("/usr/bin/cat", 2)]),
+            other,
+            snapshot(&[("/usr/bin/ls", 4)]),
+        ])
+        .unwrap();
+
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            merged.snapshot.filehashes,
+            Some(SnapshotFiles(HashSet::from([
+                ("/usr/bin/ls".to_owned(), vec![1]),
+                ("/usr/bin/ls".to_owned(), vec![4]),
+                ("/usr/bin/cat".to_owned(), vec![2]),
+                ("/usr/bin/cp".to_owned(), vec![3]),
+            ])))
+        );
+        assert_eq!(merged.snapshot.skipped_files, Some(4));
+        assert_eq!(merged.snapshot.rules, Some(SnapshotRules::default()));
+        assert_eq!(
+            merged.snapshot.pcr_values,
+            Some(PcrValues::from([(0, "00".to_owned())]))
+        );
+        assert_eq!(merged.snapshot.pcr_bank, Some(PcrBank::Sha256));
+
+        // A snapshot without files (or skipped files count) keeps the ones of the others
+        let mut empty = snapshot(&[]);
+        empty.filehashes = None;
+        empty.skipped_files = None;
+        let merged = merge_snapshots([empty, snapshot(&[("/usr/bin/ls", 1)])]).unwrap();
+        assert_eq!(
+            merged.snapshot.filehashes,
+            Some(SnapshotFiles(HashSet::from([(
+                "/usr/bin/ls".to_owned(),
+                vec![1]
+            )])))
+        );
+        assert_eq!(merged.snapshot.skipped_files, Some(1));
+
+        // The rules are dropped when they differ
+        let mut other = snapshot(&[]);
+        other.rules = Some(SnapshotRules {
+            max_file_size: Some(1024),
+            ..SnapshotRules::default()
+        });
+        let merged = merge_snapshots([snapshot(&[]), other]).unwrap();
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.snapshot.rules, None);
+
+        assert!(merge_snapshots([]).is_err());
+    }
+
+    #[test]
+    fn test_merge_snapshots_conflicts() {
+        let mut cloud_type = snapshot(&[]);
+        cloud_type.cloud_type = Some(CloudProvider::Azure);
+        let mut tee_policy = snapshot(&[]);
+        tee_policy.tee_policy = TeePolicy::Sev(SevQuoteVerificationPolicy::new([2; 48]));
+        let mut tpm_policy = snapshot(&[]);
+        tpm_policy.tpm_policy = Some(TpmPolicy::default());
+
+        let merged = merge_snapshots([
+            snapshot(&[]),
+            cloud_type,
+            tee_policy.clone(),
+            tpm_policy,
+            tee_policy,
+        ])
+        .unwrap();
+        assert_eq!(
+            merged.conflicts,
+            vec![
+                MergeConflict::CloudType,
+                MergeConflict::TeePolicy,
+                MergeConflict::TpmPolicy
+            ]
+        );
+        // The values of the first snapshot are kept
+        let first = snapshot(&[]);
+        assert_eq!(merged.snapshot.cloud_type, first.cloud_type);
+        assert_eq!(merged.snapshot.tee_policy, first.tee_policy);
+        assert_eq!(merged.snapshot.tpm_policy, first.tpm_policy);
+
+        // The PCR values conflict on a different value or a different bank
+        let mut pcr_values = snapshot(&[]);
+        pcr_values.pcr_values = Some(PcrValues::from([(0, "01".to_owned())]));
+        let mut pcr_bank = snapshot(&[]);
+        pcr_bank.pcr_bank = Some(PcrBank::Sha384);
+        for other in [pcr_values, pcr_bank] {
+            let merged = merge_snapshots([snapshot(&[]), other]).unwrap();
+            assert_eq!(merged.conflicts, vec![MergeConflict::PcrValues]);
+            assert_eq!(
+                merged.snapshot.pcr_values,
+                Some(PcrValues::from([(0, "00".to_owned())]))
+            );
+            assert_eq!(merged.snapshot.pcr_bank, Some(PcrBank::Sha256));
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use tpm_quote::PcrHashMethod;
+
+use crate::error::Error;
+
+/// The highest PCR index of a TPM 2.0
+pub const MAX_PCR: u8 = 23;
+
+/// The values (hex encoded) of PCRs by PCR index
+pub type PcrValues = BTreeMap<u8, String>;
+
+/// The concatenation of the values of the quoted PCRs, whose digest is signed in a TPM quote
+///
+/// The PCRs are concatenated in ascending order: the value of the PCR extended by IMA
+/// (`ima_pcr`) is `ima_pcr_value`, the values of the other PCRs are `pcr_values`
+pub fn quoted_pcr_values(
+    pcr_values: &PcrValues,
+    ima_pcr: u8,
+    ima_pcr_value: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut quoted = Vec::with_capacity((pcr_values.len() + 1) * ima_pcr_value.len());
+    let mut ima_pcr_value = Some(ima_pcr_value);
+
+    for (pcr, value) in pcr_values.iter().filter(|(pcr, _)| **pcr != ima_pcr) {
+        if *pcr > ima_pcr {
+            if let Some(ima_pcr_value) = ima_pcr_value.take() {
+                quoted.extend_from_slice(ima_pcr_value);
+            }
+        }
+        quoted.extend(hex::decode(value)?);
+    }
+    if let Some(ima_pcr_value) = ima_pcr_value {
+        quoted.extend_from_slice(ima_pcr_value);
+    }
+
+    Ok(quoted)
+}
+
+/// The magic number of the structures generated by the TPM (`TPM_GENERATED_VALUE`)
+const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+
+/// The type of the attestation structure of a TPM quote (`TPM_ST_ATTEST_QUOTE`)
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+
+/// The PCR bank and the PCRs attested by a TPM quote (a marshalled `TPMS_ATTEST`)
+///
+/// The selection is read from the `pcrSelect` field of the `TPMS_QUOTE_INFO`: a quote
+/// covering several banks is rejected
+pub fn quoted_pcr_selection(quote: &[u8]) -> Result<(PcrBank, PcrSelection), Error> {
+    let mut reader = QuoteReader(quote);
+
+    if reader.u32()? != TPM_GENERATED_VALUE || reader.u16()? != TPM_ST_ATTEST_QUOTE {
+        return Err(Error::Tpm(
+            "The TPM quote is not a quote attestation".to_owned(),
+        ));
+    }
+
+    // qualifiedSigner, extraData
+    for _ in 0..2 {
+        let size = reader.u16()?;
+        reader.take(usize::from(size))?;
+    }
+    // clockInfo (clock, resetCount, restartCount, safe), firmwareVersion
+    reader.take(8 + 4 + 4 + 1 + 8)?;
+
+    if reader.u32()? != 1 {
+        return Err(Error::Tpm(
+            "The TPM quote should attest a single PCR bank".to_owned(),
+        ));
+    }
+
+    let bank = match reader.u16()? {
+        0x0004 => PcrBank::Sha1,
+        0x000b => PcrBank::Sha256,
+        0x000c => PcrBank::Sha384,
+        0x000d => PcrBank::Sha512,
+        algorithm => {
+            return Err(Error::Tpm(format!(
+                "Unknown PCR bank {algorithm:#06x} in the TPM quote"
+            )))
+        }
+    };
+
+    let size = reader.u8()?;
+    let selection = reader
+        .take(usize::from(size))?
+        .iter()
+        .enumerate()
+        .flat_map(|(i, byte)| {
+            (0..8)
+                .filter(move |bit| byte & (1 << bit) != 0)
+                .map(move |bit| i * 8 + bit)
+        })
+        .map(|pcr| {
+            u8::try_from(pcr)
+                .ok()
+                .filter(|pcr| *pcr <= MAX_PCR)
+                .ok_or_else(|| Error::Tpm(format!("Unexpected PCR {pcr} in the TPM quote")))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((bank, selection))
+}
+
+/// A reader of the big-endian fields of a marshalled TPM structure
+struct QuoteReader<'a>(&'a [u8]);
+
+impl<'a> QuoteReader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < size {
+            return Err(Error::Tpm("The TPM quote is truncated".to_owned()));
+        }
+        let (bytes, remaining) = self.0.split_at(size);
+        self.0 = remaining;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+}
+
+/// A PCR bank of the TPM, given by its hash algorithm
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PcrBank {
+    Sha1,
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl From<PcrBank> for PcrHashMethod {
+    fn from(bank: PcrBank) -> Self {
+        match bank {
+            PcrBank::Sha1 => Self::Sha1,
+            PcrBank::Sha256 => Self::Sha256,
+            PcrBank::Sha384 => Self::Sha384,
+            PcrBank::Sha512 => Self::Sha512,
+        }
+    }
+}
+
+impl From<&PcrHashMethod> for PcrBank {
+    fn from(hash_method: &PcrHashMethod) -> Self {
+        match hash_method {
+            PcrHashMethod::Sha1 => Self::Sha1,
+            PcrHashMethod::Sha256 => Self::Sha256,
+            PcrHashMethod::Sha384 => Self::Sha384,
+            PcrHashMethod::Sha512 => Self::Sha512,
+        }
+    }
+}
+
+impl FromStr for PcrBank {
+    type Err = String;
+
+    fn from_str(bank: &str) -> Result<Self, Self::Err> {
+        match bank {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            "sha384" => Ok(Self::Sha384),
+            "sha512" => Ok(Self::Sha512),
+            _ => Err(format!(
+                "Unknown PCR bank '{bank}' (expecting 'sha1', 'sha256', 'sha384' or 'sha512')"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PcrBank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        })
+    }
+}
+
+/// A set of PCR indexes, written as a comma-separated list of indexes or ranges (`0-7,10`)
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PcrSelection(BTreeSet<u8>);
+
+impl PcrSelection {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub fn contains(&self, pcr: u8) -> bool {
+        self.0.contains(&pcr)
+    }
+
+    /// The PCR indexes in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Add the PCR `pcr` to the selection
+    pub fn insert(&mut self, pcr: u8) {
+        self.0.insert(pcr);
+    }
+}
+
+impl FromIterator<u8> for PcrSelection {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl FromStr for PcrSelection {
+    type Err = String;
+
+    fn from_str(selection: &str) -> Result<Self, Self::Err> {
+        let parse = |pcr: &str| {
+            pcr.trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|pcr| *pcr <= MAX_PCR)
+                .ok_or_else(|| {
+                    format!("Invalid PCR '{pcr}' (expecting an index from 0 to {MAX_PCR})")
+                })
+        };
+
+        let mut pcrs = BTreeSet::new();
+        for item in selection.split(',').filter(|item| !item.trim().is_empty()) {
+            match item.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("Invalid PCR range '{item}'"));
+                    }
+                    pcrs.extend(first..=last);
+                }
+                None => {
+                    pcrs.insert(parse(item)?);
+                }
+            }
+        }
+
+        Ok(Self(pcrs))
+    }
+}
+
+impl TryFrom<String> for PcrSelection {
+    type Error = String;
+
+    fn try_from(selection: String) -> Result<Self, Self::Error> {
+        selection.parse()
+    }
+}
+
+impl From<PcrSelection> for String {
+    fn from(selection: PcrSelection) -> Self {
+        selection.to_string()
+    }
+}
+
+impl fmt::Display for PcrSelection {
+    /// Write the selection with the consecutive indexes merged into ranges
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ranges: Vec<(u8, u8)> = vec![];
+        for pcr in self.iter() {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == pcr => *last = pcr,
+                _ => ranges.push((pcr, pcr)),
+            }
+        }
+
+        let ranges = ranges
+            .into_iter()
+            .map(|(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{first}-{last}")
+                }
+            })
+            .collect::<Vec<_>>();
+        f.write_str(&ranges.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quoted_pcr_selection, quoted_pcr_values, PcrBank, PcrSelection, PcrValues};
+
+    #[test]
+    fn test_pcr_selection() {
+        let selection: PcrSelection = "0-7, 10".parse().unwrap();
+        assert_eq!(
+            selection.iter().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 10]
+        );
+        assert_eq!(selection.to_string(), "0-7,10");
+
+        let selection: PcrSelection = "4,0,1,2,9,8".parse().unwrap();
+        assert_eq!(selection.to_string(), "0-2,4,8-9");
+
+        assert!("".parse::<PcrSelection>().unwrap().is_empty());
+        assert!("24".parse::<PcrSelection>().is_err());
+        assert!("7-0".parse::<PcrSelection>().is_err());
+        assert!("a".parse::<PcrSelection>().is_err());
+    }
+
+    #[test]
+    fn test_pcr_bank() {
+        for bank in [
+            PcrBank::Sha1,
+            PcrBank::Sha256,
+            PcrBank::Sha384,
+            PcrBank::Sha512,
+        ] {
+            assert_eq!(bank.to_string().parse::<PcrBank>().unwrap(), bank);
+            assert_eq!(serde_json::to_string(&bank).unwrap(), format!("\"{bank}\""));
+        }
+        assert!("sha3".parse::<PcrBank>().is_err());
+    }
+
+    #[test]
+    fn test_quoted_pcr_values() {
+        let pcr_values = PcrValues::from([
+            (0, "00".to_owned()),
+            (7, "07".to_owned()),
+            (14, "0e".to_owned()),
+        ]);
+        assert_eq!(
+            quoted_pcr_values(&pcr_values, 10, &[10]).unwrap(),
+            vec![0, 7, 10, 14]
+        );
+        assert_eq!(
+            quoted_pcr_values(&PcrValues::new(), 10, &[10]).unwrap(),
+            vec![10]
+        );
+        assert_eq!(
+            quoted_pcr_values(&pcr_values, 23, &[23]).unwrap(),
+            vec![0, 7, 14, 23]
+        );
+    }
+
+    #[test]
+    fn test_quoted_pcr_selection() {
+        // A marshalled TPMS_ATTEST quoting the PCRs 0, 7 and 10 of the SHA-256 bank
+        let quote = |count: u32, algorithm: u16, select: &[u8]| {
+            let mut quote = vec![0xff, 0x54, 0x43, 0x47, 0x80, 0x18];
+            quote.extend([0, 2, 0xaa, 0xbb]); // qualifiedSigner
+            quote.extend([0, 1, 0xcc]); // extraData
+            quote.extend([0; 25]); // clockInfo, firmwareVersion
+            quote.extend(count.to_be_bytes());
+            quote.extend(algorithm.to_be_bytes());
+            quote.push(u8::try_from(select.len()).unwrap());
+            quote.extend(select);
+            quote.extend([0, 1, 0xdd]); // pcrDigest
+            quote
+        };
+
+        assert_eq!(
+            quoted_pcr_selection(&quote(1, 0x000b, &[0x81, 0x04, 0x00])).unwrap(),
+            (PcrBank::Sha256, "0,7,10".parse().unwrap())
+        );
+        assert_eq!(
+            quoted_pcr_selection(&quote(1, 0x0004, &[0x00, 0x00, 0x80])).unwrap(),
+            (PcrBank::Sha1, "23".parse().unwrap())
+        );
+
+        assert!(quoted_pcr_selection(&quote(2, 0x000b, &[0x81, 0x04, 0x00])).is_err());
+        assert!(quoted_pcr_selection(&quote(1, 0x0012, &[0x81, 0x04, 0x00])).is_err());
+        assert!(quoted_pcr_selection(&quote(1, 0x000b, &[0, 0, 0, 1])).is_err());
+        assert!(quoted_pcr_selection(&quote(1, 0x000b, &[0x81, 0x04, 0x00])[..40]).is_err());
+
+        let mut not_a_quote = quote(1, 0x000b, &[0x81, 0x04, 0x00]);
+        not_a_quote[5] = 0x17;
+        assert!(quoted_pcr_selection(&not_a_quote).is_err());
+    }
+}
@@ -1,15 +1,22 @@
 use serde::de::{SeqAccess, Visitor};
 use serde::ser::{SerializeSeq, Serializer};
 use serde::{Deserialize, Deserializer, Serialize};
+use serde_bytes::{ByteBuf, Bytes};
 
 use std::collections::HashSet;
 use std::fmt;
 use tee_attestation::TeePolicy;
 use tpm_quote::policy::TpmPolicy;
 
-use crate::cloud_provider::CloudProvider;
+use crate::{
+    cloud_provider::CloudProvider,
+    pcr::{PcrBank, PcrValues},
+};
 
 /// Serializes a `HashSet<(String, Vec<u8>)>` to a json string.
+///
+/// The items are sorted to always get the same serialization for the same set.
+/// For a binary format, see `serialize_compact`
 pub fn serialize_hex<S>(
     buffer: &HashSet<(String, Vec<u8>)>,
     serializer: S,
@@ -17,13 +24,79 @@ pub fn serialize_hex<S>(
 where
     S: Serializer,
 {
-    let mut map = serializer.serialize_seq(Some(buffer.len()))?;
-    for item in buffer {
+    let mut items: Vec<_> = buffer.iter().collect();
+    items.sort_unstable();
+
+    if !serializer.is_human_readable() {
+        return serialize_compact(&items, serializer);
+    }
+
+    let mut map = serializer.serialize_seq(Some(items.len()))?;
+    for item in items {
         map.serialize_element(&(item.0.clone(), &hex::encode(&item.1)))?;
     }
     map.end()
 }
 
+/// Serializes the sorted `(path, hash)` items to a binary format
+///
+/// Each item is stored as `(prefix_len, suffix, hash)` where the path is the
+/// `prefix_len` first bytes of the previous path followed by `suffix`
+fn serialize_compact<S>(items: &[&(String, Vec<u8>)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    use serde::ser::Error;
+
+    let mut seq = serializer.serialize_seq(Some(items.len()))?;
+    let mut previous = "";
+    for (path, hash) in items {
+        let prefix_len = previous
+            .char_indices()
+            .zip(path.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or_else(|| previous.len().min(path.len()), |((i, _), _)| i);
+        seq.serialize_element(&(
+            u32::try_from(prefix_len).map_err(|e| Error::custom(e.to_string()))?,
+            &path[prefix_len..],
+            Bytes::new(hash),
+        ))?;
+        previous = path;
+    }
+    seq.end()
+}
+
+struct CompactHashSetDeserializer;
+
+impl<'de> Visitor<'de> for CompactHashSetDeserializer {
+    type Value = HashSet<(String, Vec<u8>)>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("(prefix length, path suffix, hash) sequence.")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        use serde::de::Error;
+
+        let mut new_obj = HashSet::<(String, Vec<u8>)>::new();
+        let mut previous = String::new();
+        while let Some((prefix_len, suffix, hash)) = seq.next_element::<(u32, String, ByteBuf)>()? {
+            let prefix = usize::try_from(prefix_len)
+                .ok()
+                .and_then(|prefix_len| previous.get(..prefix_len))
+                .ok_or_else(|| Error::custom(format!("Invalid path prefix length {prefix_len}")))?;
+            let path = format!("{prefix}{suffix}");
+            new_obj.insert((path.clone(), hash.into_vec()));
+            previous = path;
+        }
+
+        Ok(new_obj)
+    }
+}
+
 struct HashSetDeserializer;
 
 impl<'de> Visitor<'de> for HashSetDeserializer {
@@ -56,7 +129,11 @@ pub fn deserialize_hex<'de, D>(deserializer: D) -> Result<HashSet<(String, Vec<u
 where
     D: Deserializer<'de>,
 {
-    deserializer.deserialize_seq(HashSetDeserializer)
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_seq(HashSetDeserializer)
+    } else {
+        deserializer.deserialize_seq(CompactHashSetDeserializer)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -65,10 +142,172 @@ pub struct SnapshotFiles(
     pub  HashSet<(String, Vec<u8>)>,
 );
 
+/// The rules selecting the files hashed by the Cosmian VM Agent during a snapshot
+///
+/// A pattern matching a directory applies to its whole content
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SnapshotRules {
+    /// Glob patterns of the paths to hash even if they are excluded
+    pub include: Vec<String>,
+    /// Glob patterns of the paths to skip
+    pub exclude: Vec<String>,
+    /// Skip the files larger than that size (in bytes)
+    pub max_file_size: Option<u64>,
+    /// Follow the symbolic links
+    pub follow_symlinks: bool,
+    /// Descend into the directories mounted from another filesystem than the root one
+    pub cross_filesystems: bool,
+}
+
+impl Default for SnapshotRules {
+    fn default() -> Self {
+        Self {
+            include: vec![],
+            exclude: [
+                "/sys",
+                "/run",
+                "/proc",
+                "/lost+found",
+                "/dev",
+                "/media",
+                "/var",
+                "/tmp",
+            ]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect(),
+            max_file_size: None,
+            follow_symlinks: false,
+            cross_filesystems: true,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct CosmianVmSnapshot {
     pub cloud_type: Option<CloudProvider>,
     pub tee_policy: TeePolicy,
     pub tpm_policy: Option<TpmPolicy>,
     pub filehashes: Option<SnapshotFiles>,
+    /// The rules used to select the files of the filesystem in `filehashes`
+    pub rules: Option<SnapshotRules>,
+    /// The number of files matching the `rules` but missing from `filehashes` because they can't be read
+    pub skipped_files: Option<u64>,
+    /// The expected values of the PCRs measuring the boot chain (firmware, bootloader, Secure Boot...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pcr_values: Option<PcrValues>,
+    /// The PCR bank of `pcr_values`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pcr_bank: Option<PcrBank>,
+}
+
+/// The phases of a snapshot processed by the Cosmian VM Agent
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SnapshotPhase {
+    /// No snapshot has been ordered
+    #[default]
+    Idle,
+    /// A snapshot has been ordered and waits for the worker
+    Pending,
+    TeeQuote,
+    TpmPolicy,
+    ImaParsing,
+    FileListing,
+    Hashing,
+    /// The snapshot is over
+    Done,
+    /// The snapshot has been cancelled
+    Cancelled,
+    /// The snapshot has failed with that error
+    Failed(String),
+}
+
+impl fmt::Display for SnapshotPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Self::Failed(error) = self {
+            return write!(f, "failed: {error}");
+        }
+
+        f.write_str(match self {
+            Self::Idle => "idle",
+            Self::Pending => "waiting for the worker",
+            Self::TeeQuote => "getting the TEE policy",
+            Self::TpmPolicy => "getting the TPM policy",
+            Self::ImaParsing => "parsing the IMA measurement log",
+            Self::FileListing => "listing the files",
+            Self::Hashing => "hashing the files",
+            Self::Done => "done",
+            Self::Cancelled => "cancelled",
+            Self::Failed(_) => "failed",
+        })
+    }
+}
+
+/// The progress of the snapshot processed by the Cosmian VM Agent
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SnapshotStatus {
+    pub phase: SnapshotPhase,
+    /// The number of files to hash
+    pub files_total: u64,
+    /// The number of files already hashed (or found in the agent hash cache)
+    pub files_done: u64,
+    /// The number of bytes read to hash the files
+    pub bytes_hashed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{SnapshotFiles, SnapshotPhase};
+
+    #[test]
+    fn test_snapshot_files_serialization() {
+        let files = SnapshotFiles(HashSet::from([
+            ("/usr/bin/b".to_owned(), vec![2]),
+            ("/usr/bin/a".to_owned(), vec![1]),
+            ("/usr/bin/c".to_owned(), vec![3]),
+        ]));
+
+        let json = serde_json::to_string(&files).unwrap();
+        assert_eq!(
+            json,
+            r#"[["/usr/bin/a","01"],["/usr/bin/b","02"],["/usr/bin/c","03"]]"#
+        );
+        assert_eq!(serde_json::from_str::<SnapshotFiles>(&json).unwrap(), files);
+    }
+
+    #[test]
+    fn test_snapshot_files_compact_serialization() {
+        let files = SnapshotFiles(HashSet::from([
+            ("/usr/bin/ls".to_owned(), vec![1]),
+            ("/usr/bin/lsblk".to_owned(), vec![2]),
+            ("/usr/lib/é".to_owned(), vec![3]),
+            ("/usr/lib/è".to_owned(), vec![4]),
+            ("/".to_owned(), vec![5]),
+        ]));
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&files, &mut cbor).unwrap();
+        assert_eq!(
+            ciborium::from_reader::<SnapshotFiles, _>(cbor.as_slice()).unwrap(),
+            files
+        );
+    }
+
+    #[test]
+    fn test_snapshot_phase_serialization() {
+        let json = serde_json::to_string(&SnapshotPhase::Hashing).unwrap();
+        assert_eq!(json, r#""Hashing""#);
+
+        let failed = SnapshotPhase::Failed("no TPM".to_owned());
+        let json = serde_json::to_string(&failed).unwrap();
+        assert_eq!(json, r#"{"Failed":"no TPM"}"#);
+        assert_eq!(
+            serde_json::from_str::<SnapshotPhase>(&json).unwrap(),
+            failed
+        );
+        assert_eq!(failed.to_string(), "failed: no TPM");
+    }
 }
@@ -18,4 +18,6 @@ pub enum Error {
     IO(#[from] std::io::Error),
     #[error("{0}")]
     Unexpected(String),
+    #[error("Whitelist line {line}: {message}")]
+    Whitelist { line: usize, message: String },
 }
@@ -7,7 +7,7 @@ use std::{
 use serde::{Deserialize, Serialize};
 use tpm_quote::PcrHashMethod;
 
-use crate::error::Error;
+use crate::{error::Error, whitelist::Whitelist};
 
 const EVENT_ENTRY_SIZE: usize = 28;
 const IMA_ASCII_PATH: &str = "/sys/kernel/security/ima/ascii_runtime_measurements";
@@ -504,25 +504,118 @@ fn _template_data(
     Ok(template)
 }
 
+/// Return the offset of the entry number `entry` in the binary IMA list `data`
+///
+/// Only the headers are read. Return the size of `data` if the list has exactly `entry` entries
+pub fn binary_entry_offset(data: &[u8], entry: usize) -> Result<usize, Error> {
+    let mut cursor = 0;
+    for index in 0..entry {
+        if (cursor + EVENT_ENTRY_SIZE) >= data.len() {
+            return Err(Error::ImaParsing(format!(
+                "The IMA list has only {index} entries (entry {entry} requested)"
+            )));
+        }
+
+        let event: EventHeaderEntry =
+            bincode::deserialize(&data[cursor..(cursor + EVENT_ENTRY_SIZE)])?;
+        cursor += EVENT_ENTRY_SIZE + event.name_length as usize;
+
+        let length = bincode::deserialize::<u32>(
+            data.get(cursor..(cursor + (u32::BITS as usize / 8)))
+                .ok_or(Error::ImaParsing(
+                    "Not enough bytes in the buffer to parse IMA entry length".to_string(),
+                ))?,
+        )? as usize;
+        cursor += u32::BITS as usize / 8 + length;
+
+        if cursor > data.len() {
+            return Err(Error::ImaParsing(format!(
+                "Not enough bytes in the buffer to parse IMA entry template: {cursor} > {}",
+                data.len()
+            )));
+        }
+    }
+
+    Ok(cursor)
+}
+
+/// The size of a PCR value of the bank `pcr_hash_method`
+const fn pcr_value_size(pcr_hash_method: &PcrHashMethod) -> usize {
+    match pcr_hash_method {
+        PcrHashMethod::Sha1 => 20,
+        PcrHashMethod::Sha256 => 32,
+        PcrHashMethod::Sha384 => 48,
+        PcrHashMethod::Sha512 => 64,
+    }
+}
+
 impl Ima {
     /// Compute the PCR value from the actual IMA list
     pub fn pcr_value(&self, pcr_hash_method: PcrHashMethod) -> Result<Vec<u8>, Error> {
+        let initial = vec![0u8; pcr_value_size(&pcr_hash_method)];
+        self.pcr_value_from(pcr_hash_method, &initial)
+    }
+
+    /// Compute the PCR value by replaying the IMA list on top of the PCR value `initial`
+    ///
+    /// `initial` is the PCR value after the entries preceding this list: it allows to
+    /// replay only the entries added since a previous computation
+    pub fn pcr_value_from(
+        &self,
+        pcr_hash_method: PcrHashMethod,
+        initial: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let expected_size = pcr_value_size(&pcr_hash_method);
+        if initial.len() != expected_size {
+            return Err(Error::Unexpected(format!(
+                "Initial PCR value of {} bytes ({expected_size} expected)",
+                initial.len()
+            )));
+        }
+
+        let initial = initial.to_vec();
         Ok(match pcr_hash_method {
-            PcrHashMethod::Sha1 => self.entries.iter().fold(vec![0u8; 20], |old, entry| {
-                entry.sha1_pcr_value(&old).into()
-            }),
-            PcrHashMethod::Sha256 => self.entries.iter().fold(vec![0u8; 32], |old, entry| {
-                entry.sha256_pcr_value(&old).into()
-            }),
-            PcrHashMethod::Sha384 => self.entries.iter().fold(vec![0u8; 48], |old, entry| {
-                entry.sha384_pcr_value(&old).into()
-            }),
-            PcrHashMethod::Sha512 => self.entries.iter().fold(vec![0u8; 64], |old, entry| {
-                entry.sha512_pcr_value(&old).into()
-            }),
+            PcrHashMethod::Sha1 => self
+                .entries
+                .iter()
+                .fold(initial, |old, entry| entry.sha1_pcr_value(&old).into()),
+            PcrHashMethod::Sha256 => self
+                .entries
+                .iter()
+                .fold(initial, |old, entry| entry.sha256_pcr_value(&old).into()),
+            PcrHashMethod::Sha384 => self
+                .entries
+                .iter()
+                .fold(initial, |old, entry| entry.sha384_pcr_value(&old).into()),
+            PcrHashMethod::Sha512 => self
+                .entries
+                .iter()
+                .fold(initial, |old, entry| entry.sha512_pcr_value(&old).into()),
         })
     }
 
+    /// Compute the PCR value after each entry of the IMA list
+    ///
+    /// The first value is the initial PCR value (no entry replayed), the last one is `pcr_value`
+    #[must_use]
+    pub fn pcr_values(&self, pcr_hash_method: PcrHashMethod) -> Vec<Vec<u8>> {
+        let mut values = Vec::with_capacity(self.entries.len() + 1);
+        values.push(vec![0u8; pcr_value_size(&pcr_hash_method)]);
+
+        for entry in &self.entries {
+            let old = &values[values.len() - 1];
+            let value = match pcr_hash_method {
+                PcrHashMethod::Sha1 => entry.sha1_pcr_value(old).to_vec(),
+                PcrHashMethod::Sha256 => entry.sha256_pcr_value(old).to_vec(),
+                PcrHashMethod::Sha384 => entry.sha384_pcr_value(old).to_vec(),
+                PcrHashMethod::Sha512 => entry.sha512_pcr_value(old).to_vec(),
+            };
+            values.push(value);
+        }
+
+        values
+    }
+
     /// Return the id of the extended pcr value
     ///
     /// If the IMA is empty, the default value is: `IMA_DEFAULT_PCR_ID`
@@ -548,7 +641,7 @@ impl Ima {
     pub fn compare(
         &self,
         snapshot: &HashSet<(String, Vec<u8>)>,
-        whitelist: Option<&HashSet<(String, Vec<u8>)>>,
+        whitelist: Option<&Whitelist>,
     ) -> Self {
         // Pre-process the snapshot to be use later:
         // - Replace all whitespaces in filenames by underscores (to fit IMA filename-hint)
@@ -577,7 +670,7 @@ impl Ima {
                     // check if entry belongs to the whitelist
                     if let Some(whitelist) = whitelist  {
                         return entry.and_then(|entry| {
-                            (!whitelist.contains(&(entry.filename_hint.clone(), entry.filedata_hash.clone()))).then_some(entry.clone())
+                            (!whitelist.matches(&entry.filename_hint, &entry.filedata_hash_method, &entry.filedata_hash)).then_some(entry.clone())
                         });
                     }
 
@@ -977,6 +1070,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_incremental_pcr_value() {
+        let raw_ima = include_bytes!("../data/ima.bin");
+        let ima = Ima::try_from(raw_ima.as_slice()).expect("Can't parse IMA file");
+
+        let offset = binary_entry_offset(raw_ima, 400).expect("Can't find IMA entry");
+        let new_entries = Ima::try_from(&raw_ima[offset..]).expect("Can't parse IMA file");
+        assert_eq!(new_entries.entries, ima.entries[400..]);
+
+        let pcr_value = Ima {
+            entries: ima.entries[..400].to_vec(),
+        }
+        .pcr_value(PcrHashMethod::Sha256)
+        .expect("Can't compute pcr value");
+        assert_eq!(
+            new_entries
+                .pcr_value_from(PcrHashMethod::Sha256, &pcr_value)
+                .expect("Can't compute pcr value"),
+            ima.pcr_value(PcrHashMethod::Sha256)
+                .expect("Can't compute pcr value")
+        );
+        assert!(new_entries
+            .pcr_value_from(PcrHashMethod::Sha1, &pcr_value)
+            .is_err());
+
+        let pcr_values = ima.pcr_values(PcrHashMethod::Sha256);
+        assert_eq!(pcr_values.len(), ima.entries.len() + 1);
+        assert_eq!(pcr_values[0], vec![0; 32]);
+        assert_eq!(pcr_values[400], pcr_value);
+        assert_eq!(
+            pcr_values.last(),
+            ima.pcr_value(PcrHashMethod::Sha256).ok().as_ref()
+        );
+
+        assert_eq!(
+            binary_entry_offset(raw_ima, ima.entries.len()).expect("Can't find IMA entry"),
+            raw_ima.len()
+        );
+        assert!(binary_entry_offset(raw_ima, ima.entries.len() + 1).is_err());
+    }
+
     #[test]
     fn test_compare() {
         let raw_ima = include_bytes!("../data/ima.bin");
@@ -0,0 +1,278 @@
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{error::Error, ima::ImaHashMethod};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// The file entries of a whitelist (TOML)
+///
+/// ```toml
+/// # A binary with several legitimate versions
+/// [[file]]
+/// path = "/usr/bin/app"
+/// digests = ["sha256:5f70bf18...", "sha1:e09e048c..."]
+///
+/// # Any digest is accepted for the rotated files
+/// [[file]]
+/// path = "/var/log/**"
+/// digests = ["*"]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WhitelistFile {
+    #[serde(default)]
+    file: Vec<Spanned<WhitelistFileEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WhitelistFileEntry {
+    /// Glob pattern of the paths (as written in the IMA log)
+    path: Spanned<String>,
+    /// `*` for any digest, or the hex digest optionally prefixed by its algorithm (`sha256:...`)
+    digests: Vec<Spanned<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum WhitelistDigest {
+    Any,
+    Hash(ImaHashMethod, Vec<u8>),
+}
+
+impl WhitelistDigest {
+    fn parse(digest: &str) -> Result<Self, String> {
+        if digest == "*" {
+            return Ok(Self::Any);
+        }
+
+        let (method, hex_digest) = match digest.split_once(':') {
+            Some(("sha1", hex_digest)) => (Some(ImaHashMethod::Sha1), hex_digest),
+            Some(("sha256", hex_digest)) => (Some(ImaHashMethod::Sha256), hex_digest),
+            Some(("sha512", hex_digest)) => (Some(ImaHashMethod::Sha512), hex_digest),
+            Some((method, _)) => return Err(format!("Unknown digest algorithm '{method}'")),
+            None => (None, digest),
+        };
+
+        let hash = hex::decode(hex_digest)
+            .map_err(|e| format!("Invalid hex digest '{hex_digest}': {e}"))?;
+
+        // The algorithm of a digest without prefix is guessed from its size
+        let method = match method {
+            Some(method) => method,
+            None => [
+                ImaHashMethod::Sha1,
+                ImaHashMethod::Sha256,
+                ImaHashMethod::Sha512,
+            ]
+            .into_iter()
+            .find(|method| method.size() == hash.len())
+            .ok_or_else(|| format!("Unexpected digest size for '{hex_digest}'"))?,
+        };
+
+        if method.size() != hash.len() {
+            return Err(format!(
+                "Unexpected digest size for '{hex_digest}' ({method:?} expected)"
+            ));
+        }
+
+        Ok(Self::Hash(method, hash))
+    }
+}
+
+#[derive(Debug)]
+struct WhitelistEntry {
+    path: Pattern,
+    digests: Vec<WhitelistDigest>,
+}
+
+/// The files allowed in the IMA log even if they are not in the snapshot
+#[derive(Debug, Default)]
+pub struct Whitelist {
+    entries: Vec<WhitelistEntry>,
+}
+
+impl Whitelist {
+    /// Whether the file `path` with the digest `hash` computed with `hash_method` is whitelisted
+    #[must_use]
+    pub fn matches(&self, path: &str, hash_method: &ImaHashMethod, hash: &[u8]) -> bool {
+        self.entries.iter().any(|entry| {
+            entry.path.matches_with(path, MATCH_OPTIONS)
+                && entry.digests.iter().any(|digest| match digest {
+                    WhitelistDigest::Any => true,
+                    WhitelistDigest::Hash(method, digest) => {
+                        method == hash_method && digest.as_slice() == hash
+                    }
+                })
+        })
+    }
+}
+
+/// The line of the byte `offset` of `content`
+fn line_of(content: &str, offset: usize) -> usize {
+    content
+        .get(..offset)
+        .map_or(0, |before| before.matches('\n').count())
+        + 1
+}
+
+impl Whitelist {
+    /// Parse the legacy whitelist format: one `path hexdigest` line per file
+    ///
+    /// Return `None` if a line is not in that format
+    fn from_legacy(content: &str) -> Option<Self> {
+        let entries = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (path, digest) = line.split_once(' ')?;
+                Some(WhitelistEntry {
+                    path: Pattern::new(&Pattern::escape(path)).ok()?,
+                    digests: vec![WhitelistDigest::parse(digest.trim()).ok()?],
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self { entries })
+    }
+}
+
+impl TryFrom<&str> for Whitelist {
+    type Error = Error;
+
+    /// Parse a TOML whitelist, or a legacy one (see `from_legacy`) as a fallback
+    fn try_from(content: &str) -> Result<Self, Self::Error> {
+        let file: WhitelistFile = match toml::from_str(content) {
+            Ok(file) => file,
+            Err(e) => {
+                return Self::from_legacy(content).ok_or_else(|| Error::Whitelist {
+                    line: e.span().map_or(0, |span| line_of(content, span.start)),
+                    message: e.message().to_owned(),
+                })
+            }
+        };
+
+        let entries = file
+            .file
+            .into_iter()
+            .map(|entry| {
+                let error = |span: std::ops::Range<usize>, message| Error::Whitelist {
+                    line: line_of(content, span.start),
+                    message,
+                };
+
+                if entry.get_ref().digests.is_empty() {
+                    return Err(error(
+                        entry.span(),
+                        "No digest: use \"*\" to accept any digest".to_owned(),
+                    ));
+                }
+
+                let entry = entry.into_inner();
+                Ok(WhitelistEntry {
+                    path: Pattern::new(entry.path.get_ref()).map_err(|e| {
+                        error(
+                            entry.path.span(),
+                            format!("Invalid path pattern '{}': {e}", entry.path.get_ref()),
+                        )
+                    })?,
+                    digests: entry
+                        .digests
+                        .iter()
+                        .map(|digest| {
+                            WhitelistDigest::parse(digest.get_ref())
+                                .map_err(|message| error(digest.span(), message))
+                        })
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, ima::ImaHashMethod};
+
+    use super::Whitelist;
+
+    #[test]
+    fn test_whitelist() {
+        let whitelist = Whitelist::try_from(
+            r#"
+# A binary with several legitimate versions
+[[file]]
+path = "/usr/bin/app"
+digests = [
+    "sha1:e09e048c48301268ff38645f4c006137e42951d0",
+    "0000000000000000000000000000000000000000000000000000000000000001",
+]
+
+# Any digest is accepted for the rotated files
+[[file]]
+path = "/var/log/*.log"
+digests = ["*"]
+"#,
+        )
+        .unwrap();
+
+        let sha1 = hex::decode("e09e048c48301268ff38645f4c006137e42951d0").unwrap();
+        let mut sha256 = vec![0; 32];
+        sha256[31] = 1;
+
+        assert!(whitelist.matches("/usr/bin/app", &ImaHashMethod::Sha1, &sha1));
+        assert!(whitelist.matches("/usr/bin/app", &ImaHashMethod::Sha256, &sha256));
+        assert!(!whitelist.matches("/usr/bin/app", &ImaHashMethod::Sha256, &[0; 32]));
+        assert!(!whitelist.matches("/usr/bin/other", &ImaHashMethod::Sha1, &sha1));
+        assert!(whitelist.matches("/var/log/syslog.log", &ImaHashMethod::Sha1, &sha1));
+        assert!(!whitelist.matches("/var/log/app/app.log", &ImaHashMethod::Sha1, &sha1));
+    }
+
+    #[test]
+    fn test_whitelist_errors() {
+        let line = |content: &str| match Whitelist::try_from(content) {
+            Err(Error::Whitelist { line, .. }) => line,
+            _ => panic!("Whitelist error expected"),
+        };
+
+        assert_eq!(line("[[file]]\npath = \"/usr/bin/app\"\ndigests = [\"*\"]\n\n[[file]]\npath = \"/usr/bin/app\"\ndigests = [\"sha1:00\"]"), 7);
+        assert_eq!(
+            line("[[file]]\npath = \"/usr/bin/[\"\ndigests = [\"*\"]"),
+            2
+        );
+        assert_eq!(line("[[file]]\npath = \"/usr/bin/app\"\ndigests = []"), 1);
+        assert_eq!(
+            line("# comment\n[[file]]\npath = \"/usr/bin/app\"\ndigest = [\"*\"]"),
+            4
+        );
+        assert_eq!(line("/usr/bin/app e09e048c"), 1);
+        assert_eq!(line("/usr/bin/app"), 1);
+    }
+
+    #[test]
+    fn test_legacy_whitelist() {
+        let whitelist = Whitelist::try_from(
+            "/usr/bin/app e09e048c48301268ff38645f4c006137e42951d0\n\n/usr/bin/[a] 0000000000000000000000000000000000000000000000000000000000000001\n",
+        )
+        .unwrap();
+
+        let sha1 = hex::decode("e09e048c48301268ff38645f4c006137e42951d0").unwrap();
+        let mut sha256 = vec![0; 32];
+        sha256[31] = 1;
+
+        assert!(whitelist.matches("/usr/bin/app", &ImaHashMethod::Sha1, &sha1));
+        assert!(!whitelist.matches("/usr/bin/app", &ImaHashMethod::Sha256, &sha256));
+        assert!(!whitelist.matches("/usr/bin/ap", &ImaHashMethod::Sha1, &sha1));
+        // The paths are not glob patterns
+        assert!(whitelist.matches("/usr/bin/[a]", &ImaHashMethod::Sha256, &sha256));
+        assert!(!whitelist.matches("/usr/bin/a", &ImaHashMethod::Sha256, &sha256));
+    }
+}